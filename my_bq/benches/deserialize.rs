@@ -0,0 +1,202 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use my_bq::client::Deserialize as _;
+use my_bq::structs::job_query_results::{JobQueryResults, RawQueryResults};
+use my_bq::structs::table_schema::TableSchema;
+use my_bq::Deserialize;
+
+// The fields are only passed to black_box
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct EventValue {
+    string_value: Option<String>,
+    int_value: Option<i64>,
+    float_value: Option<f64>,
+    double_value: Option<f64>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct EventParam {
+    key: String,
+    value: EventValue,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct Event {
+    event_date: String,
+    event_timestamp: i64,
+    event_name: String,
+    user_pseudo_id: String,
+    event_params: Vec<EventParam>,
+}
+
+const SCHEMA: &str = r#"{
+    "fields": [
+      {"name": "event_date", "type": "STRING", "mode": "NULLABLE"},
+      {"name": "event_timestamp", "type": "INTEGER", "mode": "NULLABLE"},
+      {"name": "event_name", "type": "STRING", "mode": "NULLABLE"},
+      {"name": "user_pseudo_id", "type": "STRING", "mode": "NULLABLE"},
+      {
+        "name": "event_params",
+        "type": "RECORD",
+        "mode": "REPEATED",
+        "fields": [
+          {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+          {
+            "name": "value",
+            "type": "RECORD",
+            "mode": "NULLABLE",
+            "fields": [
+              {"name": "string_value", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "int_value", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "float_value", "type": "FLOAT", "mode": "NULLABLE"},
+              {"name": "double_value", "type": "FLOAT", "mode": "NULLABLE"}
+            ]
+          }
+        ]
+      }
+    ]
+  }"#;
+
+// An event parameter of each value type, cycled through
+fn event_param(i: usize, j: usize) -> String {
+    let (key, value) = match j % 3 {
+        0 => (
+            "page_location",
+            format!(r#"{{"v": "https://example.com/{}"}}, {{"v": null}}"#, i),
+        ),
+        1 => (
+            "ga_session_id",
+            format!(r#"{{"v": null}}, {{"v": "{}"}}"#, i),
+        ),
+        _ => ("engagement_time", r#"{"v": null}, {"v": null}"#.to_string()),
+    };
+    let double_value = if j % 3 == 2 { r#""1.5""# } else { "null" };
+    format!(
+        r#"{{"v": {{"f": [{{"v": "{key}"}}, {{"v": {{"f": [{value}, {{"v": null}}, {{"v": {double_value}}}]}}}}]}}}}"#,
+        key = key,
+        value = value,
+        double_value = double_value
+    )
+}
+
+// A page of query results shaped like the GA4 events export, with `params` event parameters per row
+fn page(rows: usize, params: usize) -> Vec<u8> {
+    let rows: Vec<String> = (0..rows)
+        .map(|i| {
+            let params: Vec<String> = (0..params).map(|j| event_param(i, j)).collect();
+            format!(
+                r#"{{"f": [
+                    {{"v": "20221018"}}, {{"v": "{ts}"}}, {{"v": "page_view"}}, {{"v": "{i}.1666051200"}},
+                    {{"v": [{params}]}}
+                  ]}}"#,
+                ts = 1666051200000000i64 + i as i64,
+                i = i,
+                params = params.join(",")
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"totalRows": "{len}", "jobComplete": true, "schema": {schema}, "rows": [{rows}]}}"#,
+        len = rows.len(),
+        schema = SCHEMA,
+        rows = rows.join(",")
+    )
+    .into_bytes()
+}
+
+fn deserialize_page(c: &mut Criterion) {
+    let bytes = page(1000, 3);
+    let mut group = c.benchmark_group("deserialize_page");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("table_row", |b| {
+        b.iter(|| {
+            let results: JobQueryResults = serde_json::from_slice(black_box(&bytes)).unwrap();
+            let decoder =
+                Event::create_deserialize_indices(&results.schema.unwrap().fields).unwrap();
+            let events = results
+                .rows
+                .unwrap()
+                .into_iter()
+                .map(|row| Event::deserialize(row, decoder.root()))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            black_box(events)
+        })
+    });
+    // Reference points for `raw_row`: skipping the whole page, which serde_json does without
+    // visiting the values, and building the borrowed row trees without converting them
+    group.bench_function("parse_only", |b| {
+        b.iter(|| {
+            let parsed: serde::de::IgnoredAny = serde_json::from_slice(black_box(&bytes)).unwrap();
+            black_box(parsed)
+        })
+    });
+    group.bench_function("raw_row_tree", |b| {
+        b.iter(|| {
+            let results: RawQueryResults = serde_json::from_slice(black_box(&bytes)).unwrap();
+            black_box(results)
+        })
+    });
+    group.bench_function("raw_row", |b| {
+        b.iter(|| {
+            let results: RawQueryResults = serde_json::from_slice(black_box(&bytes)).unwrap();
+            let decoder =
+                Event::create_deserialize_indices(&results.schema.unwrap().fields).unwrap();
+            let events = results
+                .rows
+                .unwrap()
+                .into_iter()
+                .map(|row| Event::deserialize_raw(row, decoder.root()))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            black_box(events)
+        })
+    });
+    group.finish();
+}
+
+// Deserializes parsed rows with growing numbers of event parameters, isolating the decoder from
+// the JSON parsing
+fn deserialize_event_params(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize_event_params");
+    for params in [1, 10, 50] {
+        let bytes = page(100, params);
+        let results: RawQueryResults = serde_json::from_slice(&bytes).unwrap();
+        let decoder = Event::create_deserialize_indices(&results.schema.unwrap().fields).unwrap();
+        let rows = results.rows.unwrap();
+        group.throughput(Throughput::Elements((rows.len() * params) as u64));
+        group.bench_function(format!("{}_params", params), |b| {
+            b.iter_batched(
+                || rows.clone(),
+                |rows| {
+                    let events = rows
+                        .into_iter()
+                        .map(|row| Event::deserialize_raw(row, decoder.root()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap();
+                    black_box(events)
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Builds the decoder from the schema, done once per page
+fn create_decoder(c: &mut Criterion) {
+    let schema: TableSchema = serde_json::from_str(SCHEMA).unwrap();
+    c.bench_function("create_decoder", |b| {
+        b.iter(|| Event::create_deserialize_indices(black_box(&schema.fields)).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    deserialize_page,
+    deserialize_event_params,
+    create_decoder
+);
+criterion_main!(benches);
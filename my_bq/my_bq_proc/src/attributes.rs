@@ -0,0 +1,301 @@
+use syn::spanned::Spanned;
+
+// Field level #[my_bq(...)] attributes
+#[derive(Default)]
+pub struct FieldAttributes {
+    // #[my_bq(rename = "name")]
+    pub rename: Option<String>,
+    // #[my_bq(alias = "name")], repeatable: other column names accepted for the field
+    pub aliases: Vec<String>,
+    // #[my_bq(default)]: use Default::default() if the column is absent from the schema
+    pub default: bool,
+    // #[my_bq(skip)]: don't read the field from the row, always use Default::default()
+    pub skip: bool,
+    // #[my_bq(with = "path")]: convert the raw value with `path::deserialize`
+    pub with: Option<syn::Path>,
+    // #[my_bq(flatten)]: read the fields of a sub-struct from the same row
+    pub flatten: bool,
+    // #[my_bq(chrono)], #[my_bq(decimal)]
+    pub imports: Imports,
+}
+
+// Crates whose types the field names without their crate path, like `Decimal` for
+// `rust_decimal::Decimal`: #[my_bq(chrono)] and #[my_bq(decimal)]
+#[derive(Default, Clone, Copy)]
+pub struct Imports {
+    pub chrono: bool,
+    pub decimal: bool,
+}
+
+// Enum variant level #[my_bq(...)] attributes
+#[derive(Default)]
+pub struct VariantAttributes {
+    // #[my_bq(rename = "name")]
+    pub rename: Option<String>,
+    // #[my_bq(other)]: catch-all variant for unknown values
+    pub other: bool,
+}
+
+// Container level #[my_bq(...)] attributes
+#[derive(Default)]
+pub struct ContainerAttributes {
+    // #[my_bq(rename_all = "...")]
+    pub rename_all: Option<RenameRule>,
+    // #[my_bq(case_insensitive)]: match column names ignoring ASCII case
+    pub case_insensitive: bool,
+    // #[my_bq(ignore_extra_columns)]: accept columns not read by any field
+    pub ignore_extra_columns: bool,
+    // #[my_bq(schema = "path")]: check the fields against a schema file at compile time
+    pub schema: Option<syn::LitStr>,
+}
+
+#[derive(Clone, Copy)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+}
+
+impl RenameRule {
+    fn from_str(rule: &syn::LitStr) -> syn::Result<Self> {
+        match rule.value().as_str() {
+            "lowercase" => Ok(RenameRule::Lower),
+            "UPPERCASE" => Ok(RenameRule::Upper),
+            "PascalCase" => Ok(RenameRule::Pascal),
+            "camelCase" => Ok(RenameRule::Camel),
+            "snake_case" => Ok(RenameRule::Snake),
+            "SCREAMING_SNAKE_CASE" => Ok(RenameRule::ScreamingSnake),
+            _ => Err(syn::Error::new_spanned(
+                rule,
+                "Unknown rename rule, expected one of \"lowercase\", \"UPPERCASE\", \"PascalCase\", \
+                 \"camelCase\", \"snake_case\", \"SCREAMING_SNAKE_CASE\"",
+            )),
+        }
+    }
+
+    // Applies the rule to a PascalCase variant name
+    pub fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::Pascal => return variant.to_string(),
+            RenameRule::Lower => return variant.to_ascii_lowercase(),
+            RenameRule::Upper => return variant.to_ascii_uppercase(),
+            _ => {}
+        }
+        let mut snake_case = String::with_capacity(variant.len() + 4);
+        for (i, c) in variant.char_indices() {
+            if c.is_ascii_uppercase() && i > 0 {
+                snake_case.push('_');
+            }
+            snake_case.push(c.to_ascii_lowercase());
+        }
+        self.apply(&snake_case)
+    }
+
+    // Applies the rule to a snake_case field name
+    pub fn apply(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_ascii_lowercase(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => field
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                        None => String::new(),
+                    }
+                })
+                .collect(),
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => String::new(),
+                }
+            }
+        }
+    }
+}
+
+// Items of all #[my_bq(...)] attributes in the list
+fn my_bq_meta_items(attrs: &[syn::Attribute]) -> syn::Result<Vec<syn::NestedMeta>> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident("my_bq") {
+            continue;
+        }
+        match attr.parse_meta()? {
+            syn::Meta::List(list) => items.extend(list.nested),
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "Expected attribute of the form #[my_bq(...)]",
+                ))
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn string_value(name_value: &syn::MetaNameValue) -> syn::Result<syn::LitStr> {
+    match &name_value.lit {
+        syn::Lit::Str(lit) => Ok(lit.clone()),
+        lit => Err(syn::Error::new_spanned(lit, "Expected string literal")),
+    }
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T, span: proc_macro2::Span) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(syn::Error::new(span, "Duplicate attribute"));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+impl VariantAttributes {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = VariantAttributes::default();
+        for item in my_bq_meta_items(attrs)? {
+            match &item {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                    set_once(&mut result.rename, string_value(nv)?.value(), nv.span())?;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("other") => {
+                    result.other = true;
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        item,
+                        "Unknown attribute, expected one of `rename = \"...\"`, `other`",
+                    ))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Imports {
+    // Sets the flag named by `item`, returning whether it names one
+    fn parse_item(&mut self, item: &syn::NestedMeta) -> bool {
+        match item {
+            syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("chrono") => self.chrono = true,
+            syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("decimal") => {
+                self.decimal = true
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Attributes of the field of a tuple struct, where only these two apply
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = Imports::default();
+        for item in my_bq_meta_items(attrs)? {
+            if !result.parse_item(&item) {
+                return Err(syn::Error::new_spanned(
+                    item,
+                    "Unknown attribute, expected one of `chrono`, `decimal`",
+                ));
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl FieldAttributes {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = FieldAttributes::default();
+        let mut span = None;
+        for item in my_bq_meta_items(attrs)? {
+            span = Some(item.span());
+            if result.imports.parse_item(&item) {
+                continue;
+            }
+            match &item {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                    set_once(&mut result.rename, string_value(nv)?.value(), nv.span())?;
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("alias") => {
+                    result.aliases.push(string_value(nv)?.value());
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("with") => {
+                    let path = string_value(nv)?.parse::<syn::Path>()?;
+                    set_once(&mut result.with, path, nv.span())?;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("default") => {
+                    result.default = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("skip") => {
+                    result.skip = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("flatten") => {
+                    result.flatten = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p))
+                    if p.is_ident("rename") || p.is_ident("alias") || p.is_ident("with") =>
+                {
+                    return Err(syn::Error::new_spanned(
+                        p,
+                        "Expected a string value, like `rename = \"...\"`",
+                    ))
+                }
+                _ => return Err(syn::Error::new_spanned(
+                    item,
+                    "Unknown attribute, expected one of `rename = \"...\"`, `alias = \"...\"`, \
+                         `default`, `skip`, `with = \"...\"`, `flatten`, `chrono`, `decimal`",
+                )),
+            }
+        }
+        let span = span.unwrap_or_else(proc_macro2::Span::call_site);
+        let renamed = result.rename.is_some() || !result.aliases.is_empty();
+        if result.skip && (renamed || result.with.is_some() || result.flatten) {
+            return Err(syn::Error::new(
+                span,
+                "`skip` can't be combined with `rename`, `alias`, `with` or `flatten`",
+            ));
+        }
+        if result.flatten && (renamed || result.with.is_some() || result.default) {
+            return Err(syn::Error::new(
+                span,
+                "`flatten` can't be combined with `rename`, `alias`, `with` or `default`",
+            ));
+        }
+        Ok(result)
+    }
+}
+
+impl ContainerAttributes {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = ContainerAttributes::default();
+        for item in my_bq_meta_items(attrs)? {
+            match &item {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv))
+                    if nv.path.is_ident("rename_all") =>
+                {
+                    let rule = RenameRule::from_str(&string_value(nv)?)?;
+                    set_once(&mut result.rename_all, rule, nv.span())?;
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("schema") => {
+                    set_once(&mut result.schema, string_value(nv)?, nv.span())?;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("case_insensitive") => {
+                    result.case_insensitive = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("ignore_extra_columns") => {
+                    result.ignore_extra_columns = true;
+                }
+                _ => return Err(syn::Error::new_spanned(
+                    item,
+                    "Unknown attribute, expected one of `rename_all = \"...\"`, `case_insensitive`, \
+                     `ignore_extra_columns`, `schema = \"...\"`",
+                )),
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,395 @@
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::DeriveInput;
+
+use crate::attributes::{ContainerAttributes, Imports, VariantAttributes};
+use crate::schema::column_schema_code;
+use crate::serialize::{check_serializable, value_code};
+use crate::{
+    bounded_generics, check_supported, column_check_code, is_custom, is_repeated, parse_type,
+    value_conversion_code, Source, SqlType,
+};
+
+pub struct Variant {
+    ident: syn::Ident,
+    name: String,
+    discriminant: i64,
+}
+
+// Types deriving the column level traits instead of the row level ones
+pub enum ColumnType {
+    // Enum with unit variants, read from STRING columns by name and from INT64 columns
+    // by discriminant
+    Enum {
+        variants: Vec<Variant>,
+        // The #[my_bq(other)] variant
+        other: Option<syn::Ident>,
+    },
+    // Single field tuple struct, mapped like its field
+    Newtype {
+        inner_ty: Box<syn::Type>,
+        sql_type: SqlType,
+    },
+}
+
+fn parse_discriminant(expr: &syn::Expr) -> syn::Result<i64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) => lit.base10_parse(),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => Ok(-parse_discriminant(expr)?),
+        _ => Err(syn::Error::new_spanned(
+            expr,
+            "Only integer literal discriminants are supported",
+        )),
+    }
+}
+
+impl ColumnType {
+    // `None` for structs with named fields, which derive the row level traits
+    pub fn parse(ast: &DeriveInput) -> syn::Result<Option<Self>> {
+        match &ast.data {
+            syn::Data::Enum(data) => {
+                let container_attrs = ContainerAttributes::parse(&ast.attrs)?;
+                let mut variants = Vec::with_capacity(data.variants.len());
+                let mut other = None;
+                let mut discriminant = 0;
+                for variant in &data.variants {
+                    if !matches!(variant.fields, syn::Fields::Unit) {
+                        return Err(syn::Error::new_spanned(
+                            &variant.fields,
+                            "Only enums with unit variants are supported",
+                        ));
+                    }
+                    let attrs = VariantAttributes::parse(&variant.attrs)?;
+                    if attrs.other {
+                        if other.is_some() {
+                            return Err(syn::Error::new_spanned(
+                                &variant.ident,
+                                "Only one variant can be marked with `other`",
+                            ));
+                        }
+                        other = Some(variant.ident.clone());
+                    }
+                    if let Some((_, expr)) = &variant.discriminant {
+                        discriminant = parse_discriminant(expr)?;
+                    }
+                    let variant_name = variant.ident.unraw().to_string();
+                    let name = match (attrs.rename, container_attrs.rename_all) {
+                        (Some(name), _) => name,
+                        (None, Some(rule)) => rule.apply_to_variant(&variant_name),
+                        (None, None) => variant_name,
+                    };
+                    variants.push(Variant {
+                        ident: variant.ident.clone(),
+                        name,
+                        discriminant,
+                    });
+                    discriminant = discriminant.wrapping_add(1);
+                }
+                Ok(Some(ColumnType::Enum { variants, other }))
+            }
+            syn::Data::Struct(syn::DataStruct {
+                fields: syn::Fields::Unnamed(fields),
+                ..
+            }) => {
+                if fields.unnamed.len() != 1 {
+                    return Err(syn::Error::new_spanned(
+                        fields,
+                        "Only tuple structs with a single field are supported",
+                    ));
+                }
+                let ty = &fields.unnamed[0].ty;
+                let imports = Imports::parse(&fields.unnamed[0].attrs)?;
+                let (inner_ty, sql_type) = parse_type(ty, imports)?;
+                check_supported(&sql_type).map_err(|msg| syn::Error::new_spanned(ty, msg))?;
+                Ok(Some(ColumnType::Newtype {
+                    inner_ty: Box::new(inner_ty),
+                    sql_type,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Generics of the type, bounding the field of newtypes by `bound` when needed
+    fn generics(&self, ast: &DeriveInput, bound: proc_macro2::TokenStream) -> syn::Generics {
+        let bounds = match self {
+            ColumnType::Newtype { inner_ty, sql_type } if is_custom(sql_type) => {
+                vec![((**inner_ty).clone(), bound)]
+            }
+            _ => Vec::new(),
+        };
+        bounded_generics(&ast.generics, bounds)
+    }
+
+    pub fn expand_deserialize(&self, ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+        let ident = &ast.ident;
+        // Fields of a column type are required, NULL values fail before reaching the newtype
+        if let (
+            ColumnType::Newtype {
+                sql_type: SqlType::Option(_),
+                ..
+            },
+            syn::Data::Struct(data),
+        ) = (self, &ast.data)
+        {
+            return Err(syn::Error::new_spanned(
+                &data.fields,
+                "Newtypes around an `Option` never read NULL values, use an `Option` of the newtype \
+                 instead",
+            ));
+        }
+        let decoder_code = match self {
+            ColumnType::Enum { .. } => quote! {
+                // The block holds a tag of 1 for INT64 columns, matched by discriminant
+                let by_discriminant = match field.field_type {
+                    ::my_bq::structs::table_field_schema::Type::String => 0,
+                    ::my_bq::structs::table_field_schema::Type::Integer
+                    | ::my_bq::structs::table_field_schema::Type::Int64 => 1,
+                    _ => {
+                        return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(
+                            ::my_bq::error::FieldMismatch::Mistyped {
+                                path: field.name.clone(),
+                                expected: "String or Integer".to_string(),
+                                found: format!("{:?}", field.field_type),
+                            }
+                            .into(),
+                        ))
+                    }
+                };
+                ops.push(::my_bq::client::Op::Tag(by_discriminant));
+                Ok(())
+            },
+            // Newtypes share the block of their field
+            ColumnType::Newtype { inner_ty, sql_type } => {
+                let check_code = column_check_code(sql_type, inner_ty);
+                // Scalars don't read the block
+                let ignore_ops = if is_custom(sql_type) {
+                    quote! {}
+                } else {
+                    quote! {let _ = ops;}
+                };
+                quote! {
+                    #ignore_ops
+                    let mut mismatch = ::my_bq::error::SchemaMismatch::default();
+                    #check_code
+                    if !mismatch.is_empty() {
+                        return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(mismatch));
+                    }
+                    Ok(())
+                }
+            }
+        };
+        let conversion_code = |source: Source| match self {
+            ColumnType::Enum { variants, other } => {
+                let type_name = ident.to_string();
+                let names = variants.iter().map(|v| &v.name);
+                let discriminants = variants.iter().map(|v| v.discriminant);
+                let name_idents = variants.iter().map(|v| &v.ident);
+                let discriminant_idents = variants.iter().map(|v| &v.ident);
+                let value_type = source.value_type();
+                let owned_val = source.owned_string();
+                let fallback = match other {
+                    Some(other) => quote! {Ok(Self::#other)},
+                    None => quote! {
+                        Err(::my_bq::error::BigQueryError::ValueConversionError {
+                            value: #owned_val,
+                            expected: #type_name,
+                        })
+                    },
+                };
+                let value_error = format!("Expected string value for {}, found {{:?}}", type_name);
+                quote! {
+                    let val = match value {
+                        #value_type::String(val) => val,
+                        other_value => {
+                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
+                                #value_error,
+                                other_value
+                            )))
+                        }
+                    };
+                    if decoder.tag() == 1 {
+                        let discriminant = match val.parse::<i64>() {
+                            Ok(discriminant) => discriminant,
+                            Err(err) => {
+                                return Err(::my_bq::error::BigQueryError::invalid_value(
+                                    "", &val, "Integer", err,
+                                ))
+                            }
+                        };
+                        match discriminant {
+                            #(#discriminants => Ok(Self::#discriminant_idents),)*
+                            _ => #fallback,
+                        }
+                    } else {
+                        match &*val {
+                            #(#names => Ok(Self::#name_idents),)*
+                            _ => #fallback,
+                        }
+                    }
+                }
+            }
+            ColumnType::Newtype { inner_ty, sql_type } => {
+                let value_code = value_conversion_code(
+                    sql_type,
+                    inner_ty,
+                    &ident.to_string(),
+                    "",
+                    &quote! {decoder},
+                    source,
+                );
+                quote! {
+                    let value = Some(value);
+                    Ok(Self(#value_code))
+                }
+            }
+        };
+        let owned_code = conversion_code(Source::Owned);
+        let raw_code = conversion_code(Source::Raw);
+        // Newtypes of records select the sub-columns of the record
+        let select_code = match self {
+            ColumnType::Newtype { inner_ty, sql_type } if is_custom(sql_type) => quote! {
+                fn select_fields() -> Vec<::my_bq::query::SelectColumn> {
+                    <#inner_ty as ::my_bq::client::ColumnDeserialize>::select_fields()
+                }
+            },
+            _ => quote! {},
+        };
+        // Newtypes around a `Vec` read the whole REPEATED column
+        let repeated_code = match self {
+            ColumnType::Newtype { sql_type, .. } if is_repeated(sql_type) => quote! {
+                fn repeated() -> bool {
+                    true
+                }
+            },
+            ColumnType::Newtype { inner_ty, sql_type } if is_custom(sql_type) => quote! {
+                fn repeated() -> bool {
+                    <#inner_ty as ::my_bq::client::ColumnDeserialize>::repeated()
+                }
+            },
+            _ => quote! {},
+        };
+        let generics = self.generics(ast, quote! {::my_bq::client::ColumnDeserialize});
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        Ok(quote! {
+            impl #impl_generics ::my_bq::client::ColumnDeserialize for #ident #ty_generics #where_clause {
+                fn create_column_decoder(
+                    field: &::my_bq::structs::table_field_schema::TableFieldSchema,
+                    ops: &mut Vec<::my_bq::client::Op>,
+                ) -> Result<(), ::my_bq::error::BigQueryError> {
+                    #decoder_code
+                }
+                fn deserialize_column(
+                    value: ::my_bq::structs::row_field::Value,
+                    decoder: ::my_bq::client::DecoderRef<'_>,
+                ) -> Result<Self, ::my_bq::error::BigQueryError> {
+                    #owned_code
+                }
+                fn deserialize_raw_column(
+                    value: ::my_bq::structs::raw_row::RawValue<'_>,
+                    decoder: ::my_bq::client::DecoderRef<'_>,
+                ) -> Result<Self, ::my_bq::error::BigQueryError> {
+                    #raw_code
+                }
+                #select_code
+                #repeated_code
+            }
+        })
+    }
+
+    pub fn expand_serialize(&self, ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+        let ident = &ast.ident;
+        let serialize_code =
+            match self {
+                // Writing the name of the variant would replace the values it caught
+                ColumnType::Enum {
+                    other: Some(other), ..
+                } => return Err(syn::Error::new_spanned(
+                    other,
+                    "Enums with an `other` variant can't be serialized, the variant doesn't keep \
+                     the value it was read from",
+                )),
+                ColumnType::Enum { variants, .. } => {
+                    let names = variants.iter().map(|v| &v.name);
+                    let idents = variants.iter().map(|v| &v.ident);
+                    quote! {
+                        let name = match self {
+                            #(Self::#idents => #names,)*
+                        };
+                        ::my_bq::serde_json::Value::String(name.to_string())
+                    }
+                }
+                ColumnType::Newtype { inner_ty, sql_type } => {
+                    if let syn::Data::Struct(data) = &ast.data {
+                        check_serializable(sql_type, &data.fields)?;
+                    }
+                    let value_code = value_code(sql_type, inner_ty);
+                    quote! {
+                        let val = &self.0;
+                        #value_code
+                    }
+                }
+            };
+        let generics = self.generics(ast, quote! {::my_bq::client::ColumnSerialize});
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        Ok(quote! {
+            impl #impl_generics ::my_bq::client::ColumnSerialize for #ident #ty_generics #where_clause {
+                fn serialize_column(&self) -> ::my_bq::serde_json::Value {
+                    #serialize_code
+                }
+            }
+        })
+    }
+
+    pub fn expand_schema(&self, ast: &DeriveInput) -> proc_macro2::TokenStream {
+        let ident = &ast.ident;
+        let schema_code = match self {
+            ColumnType::Enum { .. } => quote! {
+                ::my_bq::structs::table_field_schema::TableFieldSchema {
+                    name,
+                    field_type: ::my_bq::structs::table_field_schema::Type::String,
+                    mode,
+                    fields: None,
+                }
+            },
+            ColumnType::Newtype { inner_ty, sql_type } => {
+                // The field type may itself be nullable or repeated
+                let mode = match sql_type {
+                    SqlType::Repeated(_) => {
+                        quote! {::my_bq::structs::table_field_schema::Mode::Repeated}
+                    }
+                    SqlType::Option(subtype) if matches!(**subtype, SqlType::Repeated(_)) => {
+                        quote! {::my_bq::structs::table_field_schema::Mode::Repeated}
+                    }
+                    SqlType::Option(_) => quote! {
+                        match mode {
+                            ::my_bq::structs::table_field_schema::Mode::Repeated => mode,
+                            _ => ::my_bq::structs::table_field_schema::Mode::Nullable,
+                        }
+                    },
+                    _ => quote! {mode},
+                };
+                column_schema_code(sql_type, inner_ty, quote! {name}, mode)
+            }
+        };
+        let generics = self.generics(ast, quote! {::my_bq::client::ColumnSchema});
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+        quote! {
+            impl #impl_generics ::my_bq::client::ColumnSchema for #ident #ty_generics #where_clause {
+                fn column_schema(
+                    name: String,
+                    mode: ::my_bq::structs::table_field_schema::Mode,
+                ) -> ::my_bq::structs::table_field_schema::TableFieldSchema {
+                    #schema_code
+                }
+            }
+        }
+    }
+}
@@ -1,479 +1,1084 @@
+//! Derives of the `Deserialize`, `Serialize` and `Schema` traits of `my_bq::client`, mapping
+//! structs to the rows of BigQuery tables and query results.
+//!
+//! # Type mapping
+//!
+//! Field types map to BigQuery columns as follows:
+//! - `String` - STRING, `char` - single character STRING, `bool` - BOOL/BOOLEAN
+//! - integers of any width - INTEGER/INT64, checked for overflow; `f32`, `f64` - FLOAT/FLOAT64
+//! - with the `chrono` feature of my_bq: `chrono::DateTime<Utc>` - TIMESTAMP,
+//!   `chrono::NaiveDate` - DATE, `chrono::NaiveDateTime` - DATETIME, `chrono::NaiveTime` - TIME
+//! - with the `decimal` feature: `rust_decimal::Decimal` - NUMERIC/BIGNUMERIC
+//! - with the `bytes` feature: `Vec<u8>` - BYTES, decoded from base64. Fields of these types are
+//!   compile errors naming the feature when it is disabled
+//! - `serde_json::Value` - JSON
+//! - any other type implementing `Deserialize` - RECORD, or `ColumnDeserialize` - its own column
+//! - `Option<T>` - NULLABLE column, `None` for NULL scalars and records
+//! - `Vec<T>` - REPEATED column of scalars or records. BigQuery encodes arrays as
+//!   `{"v": [{"v": "1"}, {"v": "2"}]}` and never returns NULL arrays, they come back empty
+//! - `Option<Vec<T>>` - same as `Vec<T>`, mapping a null value to `None`
+//! - `Vec<Option<T>>` - same as `Vec<T>`, mapping null items to `None`. BigQuery arrays can't
+//!   contain NULLs, so this only matters for hand-crafted rows, and the `Serialize` derive
+//!   rejects it
+//!
+//! # Attributes
+//!
+//! Fields accept `#[my_bq(...)]` attributes:
+//! - `rename = "name"` - read the column `name` instead of the field name
+//! - `alias = "name"`, repeatable - also accept the column `name`, to read tables from several
+//!   schema versions with one struct. A schema with several matching columns is an error
+//! - `default` - use `Default::default()` when the column is absent from the schema
+//! - `skip` - don't read any column, always use `Default::default()`
+//! - `with = "path"` - convert the raw value with
+//!   `path::deserialize(Option<my_bq::structs::row_field::Value>) -> Result<T, BigQueryError>`,
+//!   skipping the schema type check
+//! - `flatten` - read the fields of another `Deserialize` struct from the same row. The columns
+//!   of the flattened struct must not overlap with the columns of the outer one
+//! - `chrono`, `decimal` - match the chrono and rust_decimal types by their bare names, like
+//!   `Decimal`, which otherwise name column types like any other imported type. Also accepted on
+//!   the field of a tuple struct
+//!
+//! The struct accepts `#[my_bq(rename_all = "...")]`, renaming all fields without an explicit
+//! `rename` to "lowercase", "UPPERCASE", "PascalCase", "camelCase", "snake_case" or
+//! "SCREAMING_SNAKE_CASE", and `#[my_bq(case_insensitive)]`, matching column names and aliases
+//! ignoring ASCII case. Combined with `rename_all = "camelCase"`, the field `user_id` then reads
+//! both `userId` and `UserId` columns.
+//!
+//! # Schema checks
+//!
+//! `create_deserialize_indices` checks the whole schema and reports every missing, extra, mistyped
+//! and ambiguous column in a `RowSchemaMismatch`, with paths into nested records like
+//! `event_params[].value.int_value`. Columns not read by any field are an error, unless the struct
+//! is marked `#[my_bq(ignore_extra_columns)]`; the attribute applies to the struct and the
+//! structs it flattens, nested records need their own. REPEATED columns are mistyped for any field
+//! but a `Vec`, or a newtype around one.
+//!
+//! `#[my_bq(schema = "schemas/events.json")]` checks the fields against a `TableSchema` JSON file,
+//! or the list of fields printed by `bq show --schema`, at compile time. The path is relative to
+//! the crate's `Cargo.toml`. Missing, extra, ambiguous and mistyped columns are compile errors;
+//! the schemas of records and column types are only checked by `create_deserialize_indices`.
+//!
+//! `select_columns` lists the columns read by the struct, for `my_bq::query::Select` to build
+//! a query selecting them: renamed fields select their column name, records only the sub-columns
+//! read by their type.
+//!
+//! Values failing to convert are reported as `BigQueryError::InvalidValue`, holding the column
+//! path, the raw value, the expected type and the underlying error; `get_results` adds the index
+//! of the row within the result set.
+//!
+//! # Column types
+//!
+//! On enums and tuple structs, the `Deserialize` derive implements
+//! `my_bq::client::ColumnDeserialize` instead, so they can be used as field types:
+//! - enums with unit variants read STRING columns by variant name and INTEGER/INT64 columns by
+//!   discriminant. Variants accept `#[my_bq(rename = "name")]`, the enum accepts `rename_all`.
+//!   Unknown values are an error, unless a variant is marked `#[my_bq(other)]` to catch them.
+//!   Such enums can't derive `Serialize`, which would write the name of that variant instead
+//! - tuple structs with a single field, like `struct UserId(String)`, read the column of their
+//!   field. The field can't be an `Option`: NULL values fail before reaching the newtype, fields
+//!   of type `Option<UserId>` read them instead
+//!
+//! # Generics and paths
+//!
+//! Generic types get the bounds their fields need, like `T: ColumnDeserialize` for a field of
+//! type `Vec<T>` or `T: Deserialize` for a flattened `T`. Types are matched by their last path
+//! segment when spelled out from `std`, `core` or `alloc`, other paths like `my_mod::Record` are
+//! records or column types.
 extern crate proc_macro;
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{quote, quote_spanned};
+use syn::ext::IdentExt;
 use syn::{parse_macro_input, DeriveInput};
 
+mod attributes;
+mod column;
+mod schema;
+mod schema_file;
+mod serialize;
+
+use attributes::{ContainerAttributes, FieldAttributes, Imports};
+use column::ColumnType;
+
 #[derive(Debug, Clone, PartialEq)]
 enum SqlType {
     String,
+    // Any integer width, converted from INT64 with an overflow check
     Integer,
     Float,
-    Record,
+    Bool,
+    // Single character STRING
+    Char,
+    // Types below need the matching cargo feature of my_bq, see `my_bq::types`
+    Timestamp,
+    Date,
+    DateTime,
+    Time,
+    Numeric,
+    Bytes,
+    Json,
+    // A record or another column type, converted through the `Column*` traits of `my_bq::client`
+    Custom,
     Option(Box<SqlType>),
     Repeated(Box<SqlType>),
 }
 
+enum FieldKind {
+    // Converted from the column of the same name, checking its schema type
+    Column {
+        inner_ty: syn::Type,
+        sql_type: SqlType,
+    },
+    // #[my_bq(with = "path")], converted by `path::deserialize` from the raw value
+    // and by `path::serialize` into it
+    With(syn::Path),
+    // #[my_bq(flatten)], a sub-struct reading its own columns from the same row
+    Flatten(syn::Type),
+    // #[my_bq(skip)]
+    Skip,
+}
+
 struct Field {
-    ty: syn::Type,
-    inner_ty: syn::Type,
-    sql_type: SqlType,
     ident: syn::Ident,
+    // Declared type of the field
+    ty: syn::Type,
     name: String,
+    // Other column names accepted for the field, from #[my_bq(alias = "...")]
+    aliases: Vec<String>,
+    kind: FieldKind,
+    // Use Default::default() if the column is absent from the schema
+    default: bool,
+    imports: Imports,
+}
+
+const INTEGER_TYPES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+];
+
+// Extracts the single type argument of `Option<T>` or `Vec<T>`
+fn single_type_argument(segment: &syn::PathSegment) -> syn::Result<&syn::Type> {
+    if let syn::PathArguments::AngleBracketed(syn::AngleBracketedGenericArguments {
+        args, ..
+    }) = &segment.arguments
+    {
+        if args.len() == 1 {
+            if let syn::GenericArgument::Type(ty) = &args[0] {
+                return Ok(ty);
+            }
+        }
+    }
+    Err(syn::Error::new_spanned(
+        segment,
+        format!("Expected exactly one type argument for {}", segment.ident),
+    ))
+}
+
+// Types converted by helpers in `my_bq::types`, matched by their crate path, like
+// `chrono::DateTime` or `chrono::prelude::DateTime`. Bare names like `DateTime` could be any
+// type, they only match when the field imports the crate with #[my_bq(chrono)] or
+// #[my_bq(decimal)]
+fn special_type(path: &syn::Path, imports: Imports) -> Option<SqlType> {
+    let last = path.segments.last()?;
+    let first = path.segments.first()?.ident.to_string();
+    let bare = path.segments.len() == 1;
+    let chrono = (bare && imports.chrono) || first == "chrono";
+    let decimal = (bare && imports.decimal) || first == "rust_decimal";
+    match last.ident.to_string().as_str() {
+        "DateTime" if chrono => Some(SqlType::Timestamp),
+        "NaiveDate" if chrono => Some(SqlType::Date),
+        "NaiveDateTime" if chrono => Some(SqlType::DateTime),
+        "NaiveTime" if chrono => Some(SqlType::Time),
+        "Decimal" if decimal => Some(SqlType::Numeric),
+        "Value" if !bare && first == "serde_json" => Some(SqlType::Json),
+        "Vec"
+            if is_std_path(path)
+                && single_type_argument(last)
+                    .map(|ty| is_type(ty, "u8"))
+                    .unwrap_or(false) =>
+        {
+            Some(SqlType::Bytes)
+        }
+        _ => None,
+    }
+}
+
+// Whether the path names a type of the standard library or a primitive, either imported like
+// `String` or spelled out like `std::string::String`
+fn is_std_path(path: &syn::Path) -> bool {
+    match path.segments.first() {
+        Some(first) if path.segments.len() > 1 => {
+            first.ident == "std" || first.ident == "core" || first.ident == "alloc"
+        }
+        _ => true,
+    }
 }
 
-fn parse_type(ty: &syn::Type) -> (syn::Type, SqlType) {
+fn parse_type(ty: &syn::Type, imports: Imports) -> syn::Result<(syn::Type, SqlType)> {
     match ty {
-        syn::Type::Path(p) => {
-            if p.path.segments.len() == 1 {
-                let id = p.path.segments[0].ident.clone();
-                if id == "String" {
-                    (ty.clone(), SqlType::String)
-                } else if id == "i64" {
-                    (ty.clone(), SqlType::Integer)
-                } else if id == "f64" {
-                    (ty.clone(), SqlType::Float)
-                } else if id == "Option" {
-                    let args = p.path.segments[0].arguments.clone();
-                    if let syn::PathArguments::AngleBracketed(
-                        syn::AngleBracketedGenericArguments { args, .. },
-                    ) = args
-                    {
-                        if args.len() != 1 {
-                            panic!("Only simple qualified types are supported, got: {:#?}", p);
-                        }
-                        match &args[0] {
-                            syn::GenericArgument::Type(ty) => {
-                                let (inner_ty, inner_sql_ty) = parse_type(ty);
-                                (inner_ty.clone(), SqlType::Option(Box::new(inner_sql_ty)))
-                            }
-                            _ => panic!("Only simple qualified types are supported, got: {:#?}", p),
-                        }
-                    } else {
-                        panic!("Only simple qualified types are supported, got: {:#?}", p);
-                    }
-                } else if id == "Vec" {
-                    let args = p.path.segments[0].arguments.clone();
-                    if let syn::PathArguments::AngleBracketed(
-                        syn::AngleBracketedGenericArguments { args, .. },
-                    ) = args
-                    {
-                        if args.len() != 1 {
-                            panic!("Only simple qualified types are supported, got: {:#?}", p);
-                        }
-                        match &args[0] {
-                            syn::GenericArgument::Type(ty) => {
-                                let (inner_ty, inner_sql_ty) = parse_type(ty);
-                                (inner_ty, SqlType::Repeated(Box::new(inner_sql_ty)))
-                            }
-                            _ => panic!("Only simple qualified types are supported, got: {:#?}", p),
-                        }
-                    } else {
-                        panic!("Only simple qualified types are supported, got: {:#?}", p);
-                    }
-                } else {
-                    // Some user custom type
-                    (ty.clone(), SqlType::Record)
-                }
+        syn::Type::Path(p) if p.qself.is_none() && special_type(&p.path, imports).is_some() => {
+            Ok((ty.clone(), special_type(&p.path, imports).unwrap()))
+        }
+        syn::Type::Path(p) if p.qself.is_none() && is_std_path(&p.path) => {
+            let segment = p
+                .path
+                .segments
+                .last()
+                .expect("paths have at least one segment");
+            let id = &segment.ident;
+            if id == "String" {
+                Ok((ty.clone(), SqlType::String))
+            } else if INTEGER_TYPES.iter().any(|t| id == t) {
+                Ok((ty.clone(), SqlType::Integer))
+            } else if id == "f64" || id == "f32" {
+                Ok((ty.clone(), SqlType::Float))
+            } else if id == "bool" {
+                Ok((ty.clone(), SqlType::Bool))
+            } else if id == "char" {
+                Ok((ty.clone(), SqlType::Char))
+            } else if id == "Option" {
+                let (inner_ty, inner_sql_ty) = parse_type(single_type_argument(segment)?, imports)?;
+                Ok((inner_ty, SqlType::Option(Box::new(inner_sql_ty))))
+            } else if id == "Vec" {
+                let (inner_ty, inner_sql_ty) = parse_type(single_type_argument(segment)?, imports)?;
+                Ok((inner_ty, SqlType::Repeated(Box::new(inner_sql_ty))))
             } else {
-                panic!("Only simple qualified types are supported, got: {:#?}", p)
+                // Some user custom type
+                Ok((ty.clone(), SqlType::Custom))
             }
         }
-        _ => panic!("Only simple qualified types are supported, got: {:#?}", ty),
+        // Some user custom type from another module, like `my_mod::Record`
+        syn::Type::Path(p) if p.qself.is_none() => Ok((ty.clone(), SqlType::Custom)),
+        _ => Err(syn::Error::new_spanned(
+            ty,
+            "Only named types are supported, like `String`, `Option<i64>` or `my_mod::MyRecord`",
+        )),
     }
 }
 
 impl Field {
-    fn new(field: &syn::Field) -> Self {
-        let field_name = if let Some(field_name) = field_name_from_attributes(&field.attrs) {
-            field_name
+    fn new(field: &syn::Field, container_attrs: &ContainerAttributes) -> syn::Result<Self> {
+        let ident = field.ident.clone().ok_or_else(|| {
+            syn::Error::new_spanned(field, "Only structs with named fields are supported")
+        })?;
+        let attrs = FieldAttributes::parse(&field.attrs)?;
+        let field_name = match (attrs.rename, container_attrs.rename_all) {
+            (Some(field_name), _) => field_name,
+            (None, Some(rule)) => rule.apply(&ident.unraw().to_string()),
+            (None, None) => ident.unraw().to_string(),
+        };
+        let kind = if attrs.skip {
+            FieldKind::Skip
+        } else if attrs.flatten {
+            FieldKind::Flatten(field.ty.clone())
+        } else if let Some(path) = attrs.with {
+            FieldKind::With(path)
         } else {
-            field.ident.clone().unwrap().to_string()
+            let (inner_ty, sql_type) = parse_type(&field.ty, attrs.imports)?;
+            check_supported(&sql_type).map_err(|msg| syn::Error::new_spanned(&field.ty, msg))?;
+            FieldKind::Column { inner_ty, sql_type }
         };
-        let (inner_ty, sql_type) = parse_type(&field.ty);
-        Field {
+        Ok(Field {
+            ident,
             ty: field.ty.clone(),
-            inner_ty,
-            sql_type,
-            ident: field.ident.clone().unwrap(),
-            name: field_name.to_string(),
+            name: field_name,
+            aliases: attrs.aliases,
+            kind,
+            default: attrs.default,
+            imports: attrs.imports,
+        })
+    }
+
+    // Inner type and sql type of the field's column. Fields with `with` keep their declared
+    // type, it has to be supported to derive `Schema`
+    fn column_type(&self) -> syn::Result<(syn::Type, SqlType)> {
+        match &self.kind {
+            FieldKind::Column { inner_ty, sql_type } => Ok((inner_ty.clone(), sql_type.clone())),
+            _ => {
+                let (inner_ty, sql_type) = parse_type(&self.ty, self.imports)?;
+                check_supported(&sql_type).map_err(|msg| syn::Error::new_spanned(&self.ty, msg))?;
+                Ok((inner_ty, sql_type))
+            }
         }
     }
 }
 
-fn sql_type_to_parse_code(sql_type: &SqlType) -> proc_macro2::TokenStream {
+// Whether the field holds custom types, possibly within Option or Vec
+fn is_custom(sql_type: &SqlType) -> bool {
+    match sql_type {
+        SqlType::Custom => true,
+        SqlType::Option(subtype) | SqlType::Repeated(subtype) => is_custom(subtype),
+        _ => false,
+    }
+}
+
+fn is_repeated(sql_type: &SqlType) -> bool {
+    match sql_type {
+        SqlType::Repeated(_) => true,
+        SqlType::Option(subtype) => is_repeated(subtype),
+        _ => false,
+    }
+}
+
+// Supported shapes are `T`, `Option<T>`, `Vec<T>`, `Option<Vec<T>>` and `Vec<Option<T>>`,
+// where T is a scalar or a record
+fn check_supported(sql_type: &SqlType) -> Result<(), &'static str> {
+    let sql_type = match sql_type {
+        SqlType::Option(subtype) => &**subtype,
+        _ => sql_type,
+    };
+    let (repeated, sql_type) = match sql_type {
+        SqlType::Repeated(subtype) => (true, &**subtype),
+        _ => (false, sql_type),
+    };
+    let sql_type = match sql_type {
+        SqlType::Option(subtype) if repeated => &**subtype,
+        _ => sql_type,
+    };
     match sql_type {
-        SqlType::String => {
-            quote! {val}
+        SqlType::Option(_) | SqlType::Repeated(_) => {
+            Err("Nested Option and Vec types are not supported")
         }
-        SqlType::Integer | SqlType::Float => {
-            quote! {val.parse()?}
+        _ => Ok(()),
+    }
+}
+
+// Row representation the generated code reads from: owned `TableRow`s, or `RawRow`s borrowing
+// the response bytes, where string values are `Cow<str>`
+#[derive(Clone, Copy)]
+enum Source {
+    Owned,
+    Raw,
+}
+
+impl Source {
+    fn value_type(self) -> proc_macro2::TokenStream {
+        match self {
+            Source::Owned => quote! {::my_bq::structs::row_field::Value},
+            Source::Raw => quote! {::my_bq::structs::raw_row::RawValue},
+        }
+    }
+
+    // Converts the string value `val` into a `String`
+    fn owned_string(self) -> proc_macro2::TokenStream {
+        match self {
+            Source::Owned => quote! {val},
+            Source::Raw => quote! {val.into_owned()},
         }
-        _ => panic!("Only simple sql type is expected here!"),
     }
 }
 
-fn field_name_from_attributes(attrs: &Vec<syn::Attribute>) -> Option<String> {
-    for attr in attrs {
-        if !attr.path.is_ident("my_bq") {
-            continue;
-        };
+// Converts the string value `val` into `ty`, wrapping errors with the `path` of the column
+fn sql_type_to_parse_code(
+    sql_type: &SqlType,
+    ty: &syn::Type,
+    path: &str,
+    source: Source,
+) -> proc_macro2::TokenStream {
+    let parse_result = match sql_type {
+        SqlType::String => return source.owned_string(),
+        SqlType::Integer if is_type(ty, "i64") => quote! {val.parse::<i64>()},
+        // `TryFrom<i64>` can't fail for `i128`, its error isn't a `BigQueryError`
+        SqlType::Integer if is_type(ty, "i128") => quote! {val.parse::<i64>().map(i128::from)},
+        SqlType::Integer => quote! {
+            val.parse::<i64>()
+                .map_err(::my_bq::error::BigQueryError::from)
+                .and_then(|int| {
+                    <#ty as ::core::convert::TryFrom<i64>>::try_from(int)
+                        .map_err(::my_bq::error::BigQueryError::from)
+                })
+        },
+        SqlType::Float | SqlType::Bool | SqlType::Char => quote! {val.parse::<#ty>()},
+        SqlType::Timestamp => {
+            feature_gated(sql_type, ty, quote! {::my_bq::types::parse_timestamp(&val)})
+        }
+        SqlType::Date => feature_gated(sql_type, ty, quote! {::my_bq::types::parse_date(&val)}),
+        SqlType::DateTime => {
+            feature_gated(sql_type, ty, quote! {::my_bq::types::parse_datetime(&val)})
+        }
+        SqlType::Time => feature_gated(sql_type, ty, quote! {::my_bq::types::parse_time(&val)}),
+        SqlType::Numeric => {
+            feature_gated(sql_type, ty, quote! {::my_bq::types::parse_numeric(&val)})
+        }
+        SqlType::Bytes => feature_gated(sql_type, ty, quote! {::my_bq::types::parse_bytes(&val)}),
+        SqlType::Json => quote! {::my_bq::types::parse_json(&val)},
+        _ => {
+            unreachable!("Only simple sql type is expected here, others are rejected in Field::new")
+        }
+    };
+    let expected = format!("{:?}", sql_type);
+    quote! {
+        match #parse_result {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                return Err(::my_bq::error::BigQueryError::invalid_value(#path, &val, #expected, err))
+            }
+        }
+    }
+}
 
-        if let Some(proc_macro2::TokenTree::Group(grp)) = attr.tokens.clone().into_iter().next() {
-            let mut stream = grp.stream().into_iter();
-            match stream.next().unwrap() {
-                proc_macro2::TokenTree::Ident(i) => {
-                    assert_eq!(i, "rename");
-                }
-                something_else => {
-                    panic!("Expected 'rename' attribute, found {}", something_else)
+// Wraps the `code` calling a helper of `my_bq::types` behind a cargo feature into the matching
+// `my_bq::__with_*` macro, which expands to a compile error naming the feature when it is
+// disabled, reported on the field type
+fn feature_gated(
+    sql_type: &SqlType,
+    ty: &syn::Type,
+    code: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let span = syn::spanned::Spanned::span(ty);
+    match sql_type {
+        SqlType::Timestamp | SqlType::Date | SqlType::DateTime | SqlType::Time => {
+            quote_spanned! {span=> ::my_bq::__with_chrono!(#code)}
+        }
+        SqlType::Numeric => quote_spanned! {span=> ::my_bq::__with_decimal!(#code)},
+        SqlType::Bytes => quote_spanned! {span=> ::my_bq::__with_bytes!(#code)},
+        _ => code,
+    }
+}
+
+fn is_type(ty: &syn::Type, name: &str) -> bool {
+    matches!(ty, syn::Type::Path(p) if p.path.is_ident(name))
+}
+
+// Converts `value: Option<Value>` into the field type. `nested_decoder` is the block of the records
+// or column types held by the field. `path` is the column path reported in conversion errors,
+// empty for the value of a column type.
+// BigQuery encodes arrays as `{"v": [{"v": "1"}, {"v": "2"}]}`, records as `{"v": {"f": [...]}}`.
+fn value_conversion_code(
+    sql_type: &SqlType,
+    ty: &syn::Type,
+    name: &str,
+    path: &str,
+    nested_decoder: &proc_macro2::TokenStream,
+    source: Source,
+) -> proc_macro2::TokenStream {
+    let value_type = source.value_type();
+    match sql_type {
+        SqlType::Option(subtype) => {
+            let inner_code = value_conversion_code(subtype, ty, name, path, nested_decoder, source);
+            quote! {
+                match value {
+                    None => None,
+                    value => Some(#inner_code),
                 }
             }
-            match stream.next().unwrap() {
-                proc_macro2::TokenTree::Punct(p) => {
-                    assert_eq!(p.as_char(), '=');
+        }
+        SqlType::Repeated(subtype) => {
+            let array_error = format!("Expected array value for field {}, found {{:?}}", name);
+            let null_error = format!("Expected array value for field {}, found null", name);
+            let item_path = format!("{}[]", path);
+            let item_code =
+                value_conversion_code(subtype, ty, name, &item_path, nested_decoder, source);
+            quote! {
+                match value {
+                    Some(#value_type::Array(values)) => {
+                        let mut items = Vec::with_capacity(values.len());
+                        for item in values {
+                            let value = item.value;
+                            items.push(#item_code);
+                        }
+                        items
+                    }
+                    Some(other_value) => {
+                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
+                            #array_error,
+                            other_value
+                        )))
+                    }
+                    None => {
+                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(
+                            #null_error.into()
+                        ))
+                    }
                 }
-                something_else => {
-                    panic!(
-                        "Expected '=' punctuation in rename attribute, found {}",
-                        something_else
-                    )
+            }
+        }
+        SqlType::Custom => {
+            let null_error = format!("Expected required value for field {}, found null", name);
+            let deserialize_column = match source {
+                Source::Owned => quote! {deserialize_column},
+                Source::Raw => quote! {deserialize_raw_column},
+            };
+            let column_context = if path.is_empty() {
+                quote! {}
+            } else {
+                quote! {.map_err(|err| err.in_column(#path))}
+            };
+            quote! {
+                match value {
+                    Some(value) => {
+                        <#ty as ::my_bq::client::ColumnDeserialize>::#deserialize_column(
+                            value,
+                            #nested_decoder,
+                        )
+                        #column_context?
+                    }
+                    None => {
+                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(
+                            #null_error.into()
+                        ))
+                    }
                 }
             }
-            match stream.next().unwrap() {
-                proc_macro2::TokenTree::Literal(l) => {
-                    let mut field_name = l.to_string();
-                    // strip quotes
-                    field_name = field_name[1..field_name.len() - 1].to_string();
-                    return Some(field_name);
+        }
+        _ => {
+            let type_error = format!("Expected string value for field {}, found {{:?}}", name);
+            let null_error = format!("Expected string value for field {}, found null", name);
+            let parse_code = sql_type_to_parse_code(sql_type, ty, path, source);
+            quote! {
+                match value {
+                    Some(#value_type::String(val)) => #parse_code,
+                    Some(other_value) => {
+                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
+                            #type_error,
+                            other_value
+                        )))
+                    }
+                    None => {
+                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(
+                            #null_error.into()
+                        ))
+                    }
                 }
-                something_else => {
-                    panic!("Expected name for the field, found {}", something_else)
+            }
+        }
+    }
+}
+
+// Schema types accepted for the given sql type; legacy and standard SQL names are both accepted
+fn expected_schema_types(sql_type: &SqlType) -> proc_macro2::TokenStream {
+    match sql_type {
+        SqlType::String | SqlType::Char => {
+            quote! {::my_bq::structs::table_field_schema::Type::String}
+        }
+        SqlType::Integer => quote! {
+            ::my_bq::structs::table_field_schema::Type::Integer
+                | ::my_bq::structs::table_field_schema::Type::Int64
+        },
+        SqlType::Float => quote! {
+            ::my_bq::structs::table_field_schema::Type::Float
+                | ::my_bq::structs::table_field_schema::Type::Float64
+        },
+        SqlType::Bool => quote! {
+            ::my_bq::structs::table_field_schema::Type::Bool
+                | ::my_bq::structs::table_field_schema::Type::Boolean
+        },
+        SqlType::Timestamp => quote! {::my_bq::structs::table_field_schema::Type::Timestamp},
+        SqlType::Date => quote! {::my_bq::structs::table_field_schema::Type::Date},
+        SqlType::DateTime => quote! {::my_bq::structs::table_field_schema::Type::Datetime},
+        SqlType::Time => quote! {::my_bq::structs::table_field_schema::Type::Time},
+        SqlType::Numeric => quote! {
+            ::my_bq::structs::table_field_schema::Type::Numeric
+                | ::my_bq::structs::table_field_schema::Type::Bignumeric
+        },
+        SqlType::Bytes => quote! {::my_bq::structs::table_field_schema::Type::Bytes},
+        SqlType::Json => quote! {::my_bq::structs::table_field_schema::Type::Json},
+        SqlType::Custom => {
+            unreachable!("Custom types check their own schema, see `column_check_code`")
+        }
+        SqlType::Option(subtype) | SqlType::Repeated(subtype) => expected_schema_types(subtype),
+    }
+}
+
+// Checks the schema `field` of a column holding `sql_type`, adding the problems to `mismatch`.
+// Custom types check their own schema and append their block to `ops`.
+fn column_check_code(sql_type: &SqlType, inner_ty: &syn::Type) -> proc_macro2::TokenStream {
+    let type_check = if is_custom(sql_type) {
+        quote! {
+            match <#inner_ty as ::my_bq::client::ColumnDeserialize>::create_column_decoder(field, ops) {
+                Ok(()) => {}
+                Err(::my_bq::error::BigQueryError::RowSchemaMismatch(other)) => {
+                    mismatch.fields.extend(other.fields)
                 }
+                Err(err) => return Err(err),
+            }
+        }
+    } else {
+        let mut column_type = sql_type;
+        while let SqlType::Option(subtype) | SqlType::Repeated(subtype) = column_type {
+            column_type = subtype;
+        }
+        let expected = format!("{:?}", column_type);
+        let expected_sql_type = expected_schema_types(sql_type);
+        quote! {
+            if !matches!(field.field_type, #expected_sql_type) {
+                mismatch.fields.push(::my_bq::error::FieldMismatch::Mistyped {
+                    path: field.name.clone(),
+                    expected: #expected.to_string(),
+                    found: format!("{:?}", field.field_type),
+                });
             }
         }
+    };
+    let repeated_check = if is_repeated(sql_type) {
+        quote! {
+            if field.mode != ::my_bq::structs::table_field_schema::Mode::Repeated {
+                mismatch.fields.push(::my_bq::error::FieldMismatch::Mistyped {
+                    path: field.name.clone(),
+                    expected: "Repeated".to_string(),
+                    found: format!("{:?}", field.mode),
+                });
+            }
+        }
+    } else {
+        quote! {}
+    };
+    quote! {
+        #type_check
+        #repeated_check
     }
-    None
 }
 
+/// Derives `my_bq::client::Deserialize` for a struct with named fields, reading its rows from
+/// query results, or `my_bq::client::ColumnDeserialize` for an enum or a tuple struct. See the
+/// [crate documentation](crate) for the type mapping, the `#[my_bq(...)]` attributes and the schema
+/// checks.
 #[proc_macro_derive(Deserialize, attributes(my_bq))]
 pub fn derive_deserialize_fn(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
-    let ident = ast.ident;
-    let fields = if let syn::Data::Struct(syn::DataStruct {
-        fields: syn::Fields::Named(syn::FieldsNamed { ref named, .. }),
-        ..
-    }) = ast.data
-    {
-        let mut fields = Vec::new();
-        for field in named {
-            fields.push(Field::new(&field));
-        }
-        fields
+    match expand_deserialize(ast) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+// Whether the schema `field` is the column of the field, by its name or one of its aliases
+fn name_match_code(f: &Field, case_insensitive: bool) -> proc_macro2::TokenStream {
+    let names = std::iter::once(&f.name).chain(&f.aliases);
+    if case_insensitive {
+        quote! {(#(field.name.eq_ignore_ascii_case(#names))||*)}
     } else {
-        panic!("Only structs with named fields are supported")
+        quote! {(#(field.name == #names)||*)}
+    }
+}
+
+/// Derives `my_bq::client::Serialize` for a struct with named fields, producing the `json` object
+/// of a row in a `tabledata.insertAll` request.
+///
+/// Fields map to values following the [type mapping](crate#type-mapping): integers are written as
+/// strings to keep their precision, non-finite floats as "NaN", "Infinity" and "-Infinity",
+/// temporal types in their canonical BigQuery format, BYTES as base64 and JSON as a string.
+/// `None` is written as null. `Vec<Option<T>>` fields are compile errors, BigQuery rejecting arrays
+/// holding NULL. The `rename`, `rename_all`, `skip` and `flatten` attributes apply, fields with
+/// `with = "path"` are converted by `path::serialize(&T) -> serde_json::Value`.
+///
+/// On enums and tuple structs, the derive implements `my_bq::client::ColumnSerialize`, writing
+/// the variant name or the single field. Enums with a `#[my_bq(other)]` variant are compile
+/// errors: the variant doesn't keep the value it caught, writing its name would replace it.
+#[proc_macro_derive(Serialize, attributes(my_bq))]
+pub fn derive_serialize_fn(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    match serialize::expand_serialize(ast) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Derives `my_bq::client::Schema` for a struct with named fields, describing the table it maps to.
+///
+/// Column types follow the [type mapping](crate#type-mapping), using the legacy type names
+/// returned by the API (INTEGER, FLOAT, BOOLEAN). `Option<T>` columns are NULLABLE, `Vec<T>` and
+/// `Option<Vec<T>>` columns REPEATED, all others REQUIRED. Records list the fields of their type,
+/// which must implement `Schema` too. The `rename`, `rename_all`, `skip` and `flatten` attributes
+/// apply; fields with `with = "path"` use the column type of their declared type.
+///
+/// On enums and tuple structs, the derive implements `my_bq::client::ColumnSchema`: enums are
+/// STRING columns, tuple structs the column of their single field.
+#[proc_macro_derive(Schema, attributes(my_bq))]
+pub fn derive_schema_fn(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    match schema::expand_schema(ast) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+// Schema matching code for a field, run for each schema `field` at position `i`
+fn schema_match_code(
+    f: &Field,
+    field_idx: usize,
+    case_insensitive: bool,
+) -> proc_macro2::TokenStream {
+    let field_name = &f.name;
+    let name_match = name_match_code(f, case_insensitive);
+    let duplicate_check = quote! {
+        let previous = ops[base + #field_idx].column();
+        if previous != usize::MAX {
+            mismatch.add_ambiguous(#field_name, &schema_fields[previous].name, &field.name);
+        }
+        claimed[i] = true;
     };
-    let mut recursive_idx = -1;
-    let fields_code1 = fields.iter().enumerate().map(|(i, f)| {
-        let field_name = f.name.clone();
-        let error = format!(
-            "Expected {:?} type for field '{}', got {{:?}}",
-            f.sql_type, field_name
-        );
-        let expected_sql_type = match f.sql_type.clone() {
-            SqlType::String => quote! {::my_bq::structs::table_field_schema::Type::String},
-            SqlType::Integer => quote! {::my_bq::structs::table_field_schema::Type::Integer},
-            SqlType::Float => quote! {::my_bq::structs::table_field_schema::Type::Float},
-            SqlType::Record => quote! {::my_bq::structs::table_field_schema::Type::Record},
-            SqlType::Option(subtype) => match *subtype {
-                SqlType::String => quote! {::my_bq::structs::table_field_schema::Type::String},
-                SqlType::Integer => quote! {::my_bq::structs::table_field_schema::Type::Integer},
-                SqlType::Float => quote! {::my_bq::structs::table_field_schema::Type::Float},
-                SqlType::Record => quote! {::my_bq::structs::table_field_schema::Type::Record},
-                _ => panic!("Unexpected subtype: {:?}", subtype),
-            },
-            SqlType::Repeated(subtype) => match *subtype {
-                SqlType::String => quote! {::my_bq::structs::table_field_schema::Type::String},
-                SqlType::Integer => quote! {::my_bq::structs::table_field_schema::Type::Integer},
-                SqlType::Float => quote! {::my_bq::structs::table_field_schema::Type::Float},
-                SqlType::Record => quote! {::my_bq::structs::table_field_schema::Type::Record},
-                _ => panic!("Unexpected subtype: {:?}", subtype),
-            },
-        };
-        let repeated_check = if let SqlType::Repeated(_) = f.sql_type.clone() {
-            let repeated_check_error = format!("Expected Repeated mode for field {}, got {{:?}}", f.name);
-            quote!{
-                if field.mode != ::my_bq::structs::table_field_schema::Mode::Repeated {
-                    return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(format!(
-                        #repeated_check_error,
-                        field.mode
-                    )));
+    let (inner_ty, sql_type) = match &f.kind {
+        FieldKind::Column { inner_ty, sql_type } => (inner_ty, sql_type),
+        FieldKind::With(_) => {
+            return quote! {
+                if #name_match {
+                    #duplicate_check
+                    ops[base + #field_idx] = ::my_bq::client::Op::Column(i);
                 }
             }
+        }
+        FieldKind::Flatten(_) | FieldKind::Skip => return quote! {},
+    };
+    // The block of custom types follows the blocks appended so far
+    let op = if is_custom(sql_type) {
+        quote! {
+            ::my_bq::client::Op::Nested {
+                column: i,
+                offset: ops.len() - base,
+            }
+        }
+    } else {
+        quote! {::my_bq::client::Op::Column(i)}
+    };
+    let check_code = column_check_code(sql_type, inner_ty);
+    // Only `Vec` fields and custom types reading the whole array, like newtypes around a `Vec`,
+    // take a REPEATED column
+    let single_check = if is_repeated(sql_type) {
+        quote! {}
+    } else {
+        let reads_array = if is_custom(sql_type) {
+            quote! {<#inner_ty as ::my_bq::client::ColumnDeserialize>::repeated()}
         } else {
-            quote!{}
+            quote! {false}
         };
-        let inner_type = f.inner_ty.clone();
-        match &f.sql_type {
-            SqlType::Record => {
-                recursive_idx += 1;
-                let recursive_error =
-                    format!("Failed to find recursive schema for field {}", field_name);
-                quote! {
-                    if field.name == #field_name {
-                        if field.field_type != #expected_sql_type {
-                            return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(format!(
-                                #error, field.field_type
-                            )));
-                        }
-                        #repeated_check
-                        match &field.fields {
-                            Some(fields) => {
-                                let decoder = #inner_type::create_deserialize_indices(&fields)?;
-                                indices[#i] = i;
-                                recursive_indices[#recursive_idx as usize] = Box::new(decoder);
-                            }
-                            None => {
-                                return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(#recursive_error.to_string()));
-                            }
-                        }
-                    }
-                }
-            },
-            SqlType::Repeated(inner) if **inner == SqlType::Record => {
-                recursive_idx += 1;
-                let recursive_error =
-                    format!("Failed to find recursive schema for field {}", field_name);
-                quote! {
-                    if field.name == #field_name {
-                        if field.field_type != #expected_sql_type {
-                            return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(format!(
-                                #error, field.field_type
-                            )));
-                        }
-                        #repeated_check
-                        match &field.fields {
-                            Some(fields) => {
-                                let decoder = #inner_type::create_deserialize_indices(&fields)?;
-                                indices[#i] = i;
-                                recursive_indices[#recursive_idx as usize] = Box::new(decoder);
-                            }
-                            None => {
-                                return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(#recursive_error.to_string()));
-                            }
-                        }
-                    }
-                }
+        quote! {
+            if field.mode == ::my_bq::structs::table_field_schema::Mode::Repeated && !#reads_array {
+                mismatch.fields.push(::my_bq::error::FieldMismatch::Mistyped {
+                    path: field.name.clone(),
+                    expected: "Nullable or Required".to_string(),
+                    found: "Repeated".to_string(),
+                });
             }
-            _ => quote! {
-                if field.name == #field_name {
-                    if field.field_type != #expected_sql_type {
-                        return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(format!(
-                            #error, field.field_type
-                        )));
-                    }
-                    #repeated_check
-                    indices[#i] = i;
-                }
+        }
+    };
+    quote! {
+        if #name_match {
+            #duplicate_check
+            ops[base + #field_idx] = #op;
+            #check_code
+            #single_check
+        }
+    }
+}
+
+// Local variable holding the value of a field, prefixed so fields named like the variables of the
+// conversion code, like `value`, aren't shadowed by them
+fn field_binding(f: &Field) -> syn::Ident {
+    quote::format_ident!("field_{}", f.ident.unraw())
+}
+
+// Conversion code for a field, binding `field_binding` from `row`
+fn field_conversion_code(f: &Field, field_idx: usize, source: Source) -> proc_macro2::TokenStream {
+    let field_ident = field_binding(f);
+    let conversion_code = match &f.kind {
+        FieldKind::Skip => {
+            return quote! {
+                let #field_ident = ::core::default::Default::default();
             }
         }
-    });
-    let fields_code2 = fields.iter().enumerate().map(|(i, f)| {
-        let error = format!("Failed to find field '{}' in schema", f.name);
+        FieldKind::Flatten(ty) => {
+            let deserialize_fields = match source {
+                Source::Owned => quote! {deserialize_fields},
+                Source::Raw => quote! {deserialize_raw_fields},
+            };
+            return quote! {
+                let #field_ident = <#ty as ::my_bq::client::Deserialize>::#deserialize_fields(
+                    row,
+                    decoder.nested(#field_idx),
+                )?;
+            };
+        }
+        FieldKind::With(path) => {
+            let name = &f.name;
+            let value = match source {
+                Source::Owned => quote! {value},
+                Source::Raw => {
+                    quote! {value.map(::my_bq::structs::raw_row::RawValue::into_value)}
+                }
+            };
+            quote! {#path::deserialize(#value).map_err(|err| err.in_column(#name))?}
+        }
+        FieldKind::Column { inner_ty, sql_type } => value_conversion_code(
+            sql_type,
+            inner_ty,
+            &f.name,
+            &f.name,
+            &quote! {decoder.nested(#field_idx)},
+            source,
+        ),
+    };
+    let read_code = quote! {
+        if row.fields.len() <= idx {
+            return Err(::my_bq::error::BigQueryError::NotEnoughFields {
+                expected: idx + 1,
+                found: row.fields.len(),
+            });
+        }
+        let value = std::mem::take(&mut row.fields[idx]).value;
+    };
+    if f.default {
         quote! {
-            if indices[#i] == usize::MAX {
-                return Err(::my_bq::error::BigQueryError::RowSchemaMismatch(
-                    #error.to_string()
-                ));
+            let idx = decoder.column(#field_idx);
+            let #field_ident = if idx == usize::MAX {
+                ::core::default::Default::default()
+            } else {
+                #read_code
+                #conversion_code
+            };
+        }
+    } else {
+        quote! {
+            let idx = decoder.column(#field_idx);
+            #read_code
+            let #field_ident = #conversion_code;
+        }
+    }
+}
+
+// Adds the column read by a field to `columns`, for `select_columns`
+fn select_column_code(f: &Field) -> proc_macro2::TokenStream {
+    let (repeated, nullable, fields) = match &f.kind {
+        FieldKind::Skip => return quote! {},
+        FieldKind::Flatten(ty) => {
+            return quote! {
+                columns.extend(<#ty as ::my_bq::client::Deserialize>::select_columns());
             }
         }
-    });
-    let fields_len = fields.len();
-    let indices_code = quote! {
-        let mut indices: Vec<usize> = vec![usize::MAX; #fields_len];
-        let mut recursive_indices: Vec<Box<::my_bq::client::Decoder>> = Vec::new();
-        for i in 0..#fields_len {
-            recursive_indices.push(Box::new(::my_bq::client::Decoder::default()));
+        FieldKind::With(_) => (false, false, quote! {Vec::new()}),
+        FieldKind::Column { inner_ty, sql_type } => {
+            let fields = if is_custom(sql_type) {
+                quote! {<#inner_ty as ::my_bq::client::ColumnDeserialize>::select_fields()}
+            } else {
+                quote! {Vec::new()}
+            };
+            (
+                is_repeated(sql_type),
+                matches!(sql_type, SqlType::Option(_)),
+                fields,
+            )
         }
-        for (i, field) in schema_fields.iter().enumerate() {
-            #(#fields_code1)*
+    };
+    let name = &f.name;
+    quote! {
+        columns.push(::my_bq::query::SelectColumn {
+            name: #name.to_string(),
+            repeated: #repeated,
+            nullable: #nullable,
+            fields: #fields,
+        });
+    }
+}
+
+fn mentions_type_param(tokens: proc_macro2::TokenStream, params: &[&syn::Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(ident) => params.iter().any(|param| **param == ident),
+        proc_macro2::TokenTree::Group(group) => mentions_type_param(group.stream(), params),
+        _ => false,
+    })
+}
+
+// Generics of the derived type, adding a `ty: bound` predicate for each of the given field types
+// mentioning a type parameter, like `T: ColumnDeserialize` for a field of type `Vec<T>`
+fn bounded_generics(
+    generics: &syn::Generics,
+    bounds: Vec<(syn::Type, proc_macro2::TokenStream)>,
+) -> syn::Generics {
+    let mut generics = generics.clone();
+    let params: Vec<syn::Ident> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let params: Vec<&syn::Ident> = params.iter().collect();
+    for (ty, bound) in bounds {
+        if mentions_type_param(quote! {#ty}, &params) {
+            generics
+                .make_where_clause()
+                .predicates
+                .push(syn::parse_quote! {#ty: #bound});
+        }
+    }
+    generics
+}
+
+// Parses the attributes and fields of a struct with named fields
+fn parse_fields(ast: &DeriveInput) -> syn::Result<(ContainerAttributes, Vec<Field>)> {
+    let container_attrs = ContainerAttributes::parse(&ast.attrs)?;
+    let fields = match &ast.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(syn::FieldsNamed { named, .. }),
+            ..
+        }) => named
+            .iter()
+            .map(|field| Field::new(field, &container_attrs))
+            .collect::<syn::Result<Vec<_>>>()?,
+        syn::Data::Struct(syn::DataStruct { fields, .. }) => {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "Only structs with named fields, tuple structs with a single field and enums are supported",
+            ))
+        }
+        syn::Data::Enum(syn::DataEnum { enum_token, .. }) => {
+            return Err(syn::Error::new_spanned(
+                enum_token,
+                "Only structs with named fields, tuple structs with a single field and enums are supported",
+            ))
+        }
+        syn::Data::Union(syn::DataUnion { union_token, .. }) => {
+            return Err(syn::Error::new_spanned(
+                union_token,
+                "Only structs with named fields, tuple structs with a single field and enums are supported",
+            ))
         }
-        // check that all indices are filled
-        #(#fields_code2)*
-        Ok(::my_bq::client::Decoder {
-            indices,
-            recursive_indices,
-        })
     };
+    Ok((container_attrs, fields))
+}
 
-    let mut recursive_idx = -1;
-    let fields_code3 = fields.iter().enumerate().map(|(i, f)| {
-        let field_ident = f.ident.clone();
-        let field_name = f.name.clone();
-        let field_name_literal = format!("{}", field_name);
-        let error1 = format!(
-            "Expected string value for field {}, found {{:?}}",
-            field_name_literal
-        );
-        let error2 = format!(
-            "Expected string value for field {}, found null",
-            field_name_literal
-        );
-        match &f.sql_type {
-            SqlType::String | SqlType::Integer | SqlType::Float => {
-                let parse_code = sql_type_to_parse_code(&f.sql_type);
-                quote! {
-                    let idx = decoder.indices[#i];
-                    if row.fields.len() <= idx {
-                        return Err(::my_bq::error::BigQueryError::NotEnoughFields {
-                            expected: idx + 1,
-                            found: row.fields.len(),
-                        });
-                    }
-                    let #field_ident = std::mem::take(&mut row.fields[idx]);
-                    let #field_ident = match #field_ident.value {
-                        Some(my_bq::structs::row_field::Value::String(val)) => #parse_code,
-                        Some(other_value) => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #error1,
-                                other_value
-                            )))
-                        }
-                        None => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(
-                                #error2.into()
-                            ))
-                        }
-                    };
-                }
-            }
-            SqlType::Record => {
-                recursive_idx += 1;
-                let null_error =
-                    format!("Expected required value for field {}, found null", f.name);
-                let type_error =
-                    format!("Expected Record value for field {}, found {{:?}}", f.name);
-                let recursive_type = &f.inner_ty;
-                quote! {
-                    let idx = decoder.indices[#i];
-                    if row.fields.len() <= idx {
-                        return Err(::my_bq::error::BigQueryError::NotEnoughFields {
-                            expected: idx + 1,
-                            found: row.fields.len(),
-                        });
-                    }
-                    let #field_ident = std::mem::take(&mut row.fields[idx]);
-                    let #field_ident = match #field_ident.value {
-                        Some(my_bq::structs::row_field::Value::Record(val)) => {
-                            #recursive_type::deserialize(val, &decoder.recursive_indices[#recursive_idx as usize])?
-                        }
-                        None => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #null_error,
-                            )))
-                        }
-                        Some(other_value) => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #type_error,
-                                other_value
-                            )))
-                        }
-                    };
-                }
+fn expand_deserialize(ast: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if let Some(column_type) = ColumnType::parse(&ast)? {
+        return column_type.expand_deserialize(&ast);
+    }
+    let (container_attrs, fields) = parse_fields(&ast)?;
+    let schema_file_code = match &container_attrs.schema {
+        Some(schema) => schema_file::check_fields(schema, &fields, &container_attrs)?,
+        None => quote! {},
+    };
+    let ident = ast.ident;
+
+    let schema_match_code = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| schema_match_code(f, i, container_attrs.case_insensitive));
+    let flatten_code = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| match &f.kind {
+            FieldKind::Flatten(ty) => Some(quote! {
+                ops[base + #i] = ::my_bq::client::Op::Flatten {
+                    offset: ops.len() - base,
+                };
+                <#ty as ::my_bq::client::Deserialize>::create_partial_decoder_ops(
+                    schema_fields,
+                    claimed,
+                    mismatch,
+                    ops,
+                )?;
+            }),
+            _ => None,
+        });
+    let missing_fields_code = fields.iter().enumerate().filter_map(|(i, f)| {
+        let required = matches!(f.kind, FieldKind::Column { .. } | FieldKind::With(_));
+        if !required || f.default {
+            return None;
+        }
+        let name = &f.name;
+        Some(quote! {
+            if ops[base + #i].column() == usize::MAX {
+                mismatch.fields.push(::my_bq::error::FieldMismatch::Missing {
+                    path: #name.to_string(),
+                });
             }
-            SqlType::Option(subtype) => {
-                let parse_code = sql_type_to_parse_code(&subtype);
-                quote! {
-                    let idx = decoder.indices[#i];
-                    if row.fields.len() <= idx {
-                        return Err(::my_bq::error::BigQueryError::NotEnoughFields {
-                            expected: idx + 1,
-                            found: row.fields.len(),
-                        });
-                    }
-                    let #field_ident = std::mem::take(&mut row.fields[idx]);
-                    let #field_ident = match #field_ident.value {
-                        Some(my_bq::structs::row_field::Value::String(val)) => Some(#parse_code),
-                        None => None,
-                        Some(other_value) => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #error1,
-                                other_value
-                            )))
-                        }
-                    };
+        })
+    });
+    let fields_len = fields.len();
+    let ops_code = quote! {
+        // one op per field, followed by the blocks of records and flattened structs
+        let base = ops.len();
+        ops.resize(base + #fields_len, ::my_bq::client::Op::Column(usize::MAX));
+        for (i, field) in schema_fields.iter().enumerate() {
+            #(#schema_match_code)*
+        }
+        #(#flatten_code)*
+        // check that all required columns are found
+        #(#missing_fields_code)*
+        Ok(())
+    };
+    let extra_columns_code = if container_attrs.ignore_extra_columns {
+        quote! {}
+    } else {
+        quote! {
+            for (field, claimed) in schema_fields.iter().zip(claimed) {
+                if !claimed {
+                    mismatch.fields.push(::my_bq::error::FieldMismatch::Extra {
+                        path: field.name.clone(),
+                    });
                 }
             }
-            SqlType::Repeated(subtype) if **subtype == SqlType::Record => {
-                recursive_idx += 1;
-                let record_type = f.ty.clone();
-                let record_inner_type = f.inner_ty.clone();
-                let error1 = format!("Expected record value for items within field {}, found {{:?}}", f.name);
-                let error2 = format!("Expected string value for field {}, found {{:?}}", f.name);
-                let error3 = format!("Expected required value for field {}, found null", f.name);
-                quote! {
-                    let idx = decoder.indices[#i];
-                    if row.fields.len() <= idx {
-                        return Err(::my_bq::error::BigQueryError::NotEnoughFields {
-                            expected: idx + 1,
-                            found: row.fields.len(),
-                        });
-                    }
-                    let mut #field_ident: #record_type = Vec::new();
-                    let params = std::mem::take(&mut row.fields[idx]);
-                    match params.value {
-                        Some(my_bq::structs::row_field::Value::Array(values)) => {
-                            for val in values {
-                                match val.value {
-                                    Some(my_bq::structs::row_field::Value::Record(val)) => {
-                                        #field_ident.push(#record_inner_type::deserialize(
-                                            val,
-                                            &decoder.recursive_indices[#recursive_idx as usize],
-                                        )?);
-                                    }
-                                    other_value => {
-                                        return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                            #error1,
-                                            other_value
-                                        )))
-                                    }
-                                }
-                            }
-                        }
-                        Some(other_value) => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #error2,
-                                other_value
-                            )))
-                        }
-                        None => {
-                            return Err(::my_bq::error::BigQueryError::UnexpectedFieldType(format!(
-                                #error3,
-                            )))
-                        }
-                    };
-                }
-            },
-            SqlType::Repeated(_) => panic!("")
         }
-    });
-    let field_names = fields.iter().map(|f| f.ident.clone());
-    let deserialize_code = quote! {
-        #(#fields_code3)*
-        Ok(Self { #(#field_names,)* })
     };
 
-    let res = quote! {
-        impl ::my_bq::client::Deserialize for #ident {
-            fn create_deserialize_indices(
-                schema_fields: &Vec<::my_bq::structs::table_field_schema::TableFieldSchema>,
-            ) -> Result<::my_bq::client::Decoder, ::my_bq::error::BigQueryError> {
-                #indices_code
+    let select_code = fields.iter().map(select_column_code);
+
+    let field_names: Vec<_> = fields.iter().map(|f| &f.ident).collect();
+    let field_bindings: Vec<_> = fields.iter().map(field_binding).collect();
+    let deserialize_code = |source| {
+        let conversion_code = fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| field_conversion_code(f, i, source));
+        quote! {
+            #(#conversion_code)*
+            Ok(Self { #(#field_names: #field_bindings,)* })
+        }
+    };
+    let owned_code = deserialize_code(Source::Owned);
+    let raw_code = deserialize_code(Source::Raw);
+    let bounds = fields
+        .iter()
+        .filter_map(|f| match &f.kind {
+            FieldKind::Column { inner_ty, sql_type } if is_custom(sql_type) => Some((
+                inner_ty.clone(),
+                quote! {::my_bq::client::ColumnDeserialize},
+            )),
+            FieldKind::Flatten(ty) => Some((ty.clone(), quote! {::my_bq::client::Deserialize})),
+            _ => None,
+        })
+        .collect();
+    let generics = bounded_generics(&ast.generics, bounds);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        #schema_file_code
+        impl #impl_generics ::my_bq::client::Deserialize for #ident #ty_generics #where_clause {
+            fn create_decoder_ops(
+                schema_fields: &[::my_bq::structs::table_field_schema::TableFieldSchema],
+                ops: &mut Vec<::my_bq::client::Op>,
+            ) -> Result<(), ::my_bq::error::BigQueryError> {
+                let mut claimed = vec![false; schema_fields.len()];
+                let mut mismatch = ::my_bq::error::SchemaMismatch::default();
+                Self::create_partial_decoder_ops(
+                    schema_fields,
+                    &mut claimed,
+                    &mut mismatch,
+                    ops,
+                )?;
+                #extra_columns_code
+                if mismatch.is_empty() {
+                    Ok(())
+                } else {
+                    Err(::my_bq::error::BigQueryError::RowSchemaMismatch(mismatch))
+                }
+            }
+            fn create_partial_decoder_ops(
+                schema_fields: &[::my_bq::structs::table_field_schema::TableFieldSchema],
+                claimed: &mut [bool],
+                mismatch: &mut ::my_bq::error::SchemaMismatch,
+                ops: &mut Vec<::my_bq::client::Op>,
+            ) -> Result<(), ::my_bq::error::BigQueryError> {
+                #ops_code
+            }
+            fn select_columns() -> Vec<::my_bq::query::SelectColumn> {
+                let mut columns = Vec::new();
+                #(#select_code)*
+                columns
+            }
+            fn deserialize(mut row: ::my_bq::TableRow, decoder: ::my_bq::client::DecoderRef<'_>) -> Result<Self, ::my_bq::error::BigQueryError> {
+                Self::deserialize_fields(&mut row, decoder)
             }
-            fn deserialize(mut row: ::my_bq::TableRow, decoder: &::my_bq::client::Decoder) -> Result<Self, ::my_bq::error::BigQueryError> {
-                #deserialize_code
+            fn deserialize_fields(row: &mut ::my_bq::TableRow, decoder: ::my_bq::client::DecoderRef<'_>) -> Result<Self, ::my_bq::error::BigQueryError> {
+                #owned_code
+            }
+            fn deserialize_raw(mut row: ::my_bq::structs::raw_row::RawRow<'_>, decoder: ::my_bq::client::DecoderRef<'_>) -> Result<Self, ::my_bq::error::BigQueryError> {
+                Self::deserialize_raw_fields(&mut row, decoder)
+            }
+            fn deserialize_raw_fields(row: &mut ::my_bq::structs::raw_row::RawRow<'_>, decoder: ::my_bq::client::DecoderRef<'_>) -> Result<Self, ::my_bq::error::BigQueryError> {
+                #raw_code
             }
         }
-    }
-    .into();
-    res
+    })
 }
@@ -0,0 +1,103 @@
+use quote::quote;
+use syn::DeriveInput;
+
+use crate::column::ColumnType;
+use crate::{bounded_generics, is_custom, is_repeated, parse_fields, FieldKind, SqlType};
+
+// Column type of the innermost type, legacy names are used as returned by the API
+fn schema_type(sql_type: &SqlType) -> proc_macro2::TokenStream {
+    match sql_type {
+        SqlType::String | SqlType::Char => {
+            quote! {::my_bq::structs::table_field_schema::Type::String}
+        }
+        SqlType::Integer => quote! {::my_bq::structs::table_field_schema::Type::Integer},
+        SqlType::Float => quote! {::my_bq::structs::table_field_schema::Type::Float},
+        SqlType::Bool => quote! {::my_bq::structs::table_field_schema::Type::Boolean},
+        SqlType::Timestamp => quote! {::my_bq::structs::table_field_schema::Type::Timestamp},
+        SqlType::Date => quote! {::my_bq::structs::table_field_schema::Type::Date},
+        SqlType::DateTime => quote! {::my_bq::structs::table_field_schema::Type::Datetime},
+        SqlType::Time => quote! {::my_bq::structs::table_field_schema::Type::Time},
+        SqlType::Numeric => quote! {::my_bq::structs::table_field_schema::Type::Numeric},
+        SqlType::Bytes => quote! {::my_bq::structs::table_field_schema::Type::Bytes},
+        SqlType::Json => quote! {::my_bq::structs::table_field_schema::Type::Json},
+        SqlType::Custom => unreachable!("Custom types describe their own column"),
+        SqlType::Option(subtype) | SqlType::Repeated(subtype) => schema_type(subtype),
+    }
+}
+
+pub fn schema_mode(sql_type: &SqlType) -> proc_macro2::TokenStream {
+    if is_repeated(sql_type) {
+        quote! {::my_bq::structs::table_field_schema::Mode::Repeated}
+    } else if matches!(sql_type, SqlType::Option(_)) {
+        quote! {::my_bq::structs::table_field_schema::Mode::Nullable}
+    } else {
+        quote! {::my_bq::structs::table_field_schema::Mode::Required}
+    }
+}
+
+// A `TableFieldSchema` expression for a column holding `sql_type`
+pub fn column_schema_code(
+    sql_type: &SqlType,
+    inner_ty: &syn::Type,
+    name: proc_macro2::TokenStream,
+    mode: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if is_custom(sql_type) {
+        quote! {<#inner_ty as ::my_bq::client::ColumnSchema>::column_schema(#name, #mode)}
+    } else {
+        let field_type = schema_type(sql_type);
+        quote! {
+            ::my_bq::structs::table_field_schema::TableFieldSchema {
+                name: #name,
+                field_type: #field_type,
+                mode: #mode,
+                fields: None,
+            }
+        }
+    }
+}
+
+pub fn expand_schema(ast: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if let Some(column_type) = ColumnType::parse(&ast)? {
+        return Ok(column_type.expand_schema(&ast));
+    }
+    let (_, fields) = parse_fields(&ast)?;
+    let ident = ast.ident;
+    let mut fields_code = Vec::with_capacity(fields.len());
+    let mut bounds = Vec::new();
+    for f in &fields {
+        let name = &f.name;
+        fields_code.push(match &f.kind {
+            FieldKind::Column { .. } | FieldKind::With(_) => {
+                let (inner_ty, sql_type) = f.column_type()?;
+                if is_custom(&sql_type) {
+                    bounds.push((inner_ty.clone(), quote! {::my_bq::client::ColumnSchema}));
+                }
+                let mode = schema_mode(&sql_type);
+                let schema_code =
+                    column_schema_code(&sql_type, &inner_ty, quote! {#name.to_string()}, mode);
+                quote! {
+                    fields.push(#schema_code);
+                }
+            }
+            FieldKind::Flatten(ty) => {
+                bounds.push((ty.clone(), quote! {::my_bq::client::Schema}));
+                quote! {
+                    fields.extend(<#ty as ::my_bq::client::Schema>::schema_fields());
+                }
+            }
+            FieldKind::Skip => quote! {},
+        });
+    }
+    let generics = bounded_generics(&ast.generics, bounds);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::my_bq::client::Schema for #ident #ty_generics #where_clause {
+            fn schema_fields() -> Vec<::my_bq::structs::table_field_schema::TableFieldSchema> {
+                let mut fields = Vec::new();
+                #(#fields_code)*
+                fields
+            }
+        }
+    })
+}
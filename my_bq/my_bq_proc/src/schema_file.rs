@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use quote::quote;
+
+use crate::attributes::ContainerAttributes;
+use crate::{is_custom, is_repeated, Field, FieldKind, SqlType};
+
+// A column of the schema file
+struct Column {
+    name: String,
+    field_type: String,
+    mode: String,
+}
+
+// Reads the columns of a `TableSchema` JSON file, `{"fields": [...]}`, or of the bare list of
+// fields printed by `bq show --schema`
+fn load_columns(path: &Path) -> Result<Vec<Column>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read schema file {}: {}", path.display(), err))?;
+    let json: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|err| format!("Failed to parse schema file {}: {}", path.display(), err))?;
+    let fields = match &json {
+        serde_json::Value::Array(fields) => fields,
+        serde_json::Value::Object(schema) => match schema.get("fields") {
+            Some(serde_json::Value::Array(fields)) => fields,
+            _ => return Err("Expected a `fields` array in the schema file".to_string()),
+        },
+        _ => return Err("Expected a table schema object or a list of fields".to_string()),
+    };
+    fields
+        .iter()
+        .map(|field| {
+            let string = |key: &str| field.get(key).and_then(serde_json::Value::as_str);
+            let name = string("name").ok_or("Expected a `name` for each field of the schema")?;
+            let field_type =
+                string("type").ok_or_else(|| format!("Expected a `type` for field '{}'", name))?;
+            Ok(Column {
+                name: name.to_string(),
+                field_type: field_type.to_ascii_uppercase(),
+                mode: string("mode").unwrap_or("NULLABLE").to_ascii_uppercase(),
+            })
+        })
+        .collect()
+}
+
+// Schema types accepted for the given sql type, `None` for custom types checking their own schema
+// at runtime. Same as `expected_schema_types`, also accepting the standard SQL names of records.
+fn accepted_types(sql_type: &SqlType) -> Option<&'static [&'static str]> {
+    let types: &'static [&'static str] = match sql_type {
+        SqlType::String | SqlType::Char => &["STRING"],
+        SqlType::Integer => &["INTEGER", "INT64"],
+        SqlType::Float => &["FLOAT", "FLOAT64"],
+        SqlType::Bool => &["BOOL", "BOOLEAN"],
+        SqlType::Timestamp => &["TIMESTAMP"],
+        SqlType::Date => &["DATE"],
+        SqlType::DateTime => &["DATETIME"],
+        SqlType::Time => &["TIME"],
+        SqlType::Numeric => &["NUMERIC", "BIGNUMERIC"],
+        SqlType::Bytes => &["BYTES"],
+        SqlType::Json => &["JSON"],
+        SqlType::Custom => return None,
+        SqlType::Option(subtype) | SqlType::Repeated(subtype) => return accepted_types(subtype),
+    };
+    Some(types)
+}
+
+fn push_error(errors: &mut Option<syn::Error>, error: syn::Error) {
+    match errors {
+        Some(errors) => errors.combine(error),
+        None => *errors = Some(error),
+    }
+}
+
+// Checks the fields against the schema file of #[my_bq(schema = "...")], the same way
+// `create_deserialize_indices` checks the schema of a result. Returns code tracking the file, so
+// the crate gets rebuilt when it changes.
+pub fn check_fields(
+    schema: &syn::LitStr,
+    fields: &[Field],
+    container_attrs: &ContainerAttributes,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| syn::Error::new_spanned(schema, "CARGO_MANIFEST_DIR is not set"))?;
+    let path = Path::new(&manifest_dir).join(schema.value());
+    let columns = load_columns(&path).map_err(|msg| syn::Error::new_spanned(schema, msg))?;
+
+    let mut errors = None;
+    let mut claimed = vec![false; columns.len()];
+    let mut flattened = false;
+    for f in fields {
+        if matches!(f.kind, FieldKind::Skip) {
+            continue;
+        }
+        if matches!(f.kind, FieldKind::Flatten(_)) {
+            // the columns of the flattened struct aren't known here
+            flattened = true;
+            continue;
+        }
+        let matching: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| {
+                std::iter::once(&f.name).chain(&f.aliases).any(|name| {
+                    if container_attrs.case_insensitive {
+                        column.name.eq_ignore_ascii_case(name)
+                    } else {
+                        column.name == *name
+                    }
+                })
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let column = match matching.as_slice() {
+            [] if f.default => continue,
+            [] => {
+                let msg = format!("Field '{}' is not in the schema file", f.name);
+                push_error(&mut errors, syn::Error::new_spanned(&f.ident, msg));
+                continue;
+            }
+            [i] => {
+                claimed[*i] = true;
+                &columns[*i]
+            }
+            _ => {
+                let names: Vec<&str> = matching.iter().map(|i| columns[*i].name.as_str()).collect();
+                let msg = format!(
+                    "Field '{}' matches several columns of the schema file: {}",
+                    f.name,
+                    names.join(", ")
+                );
+                push_error(&mut errors, syn::Error::new_spanned(&f.ident, msg));
+                matching.iter().for_each(|i| claimed[*i] = true);
+                continue;
+            }
+        };
+        let sql_type = match &f.kind {
+            FieldKind::Column { sql_type, .. } => sql_type,
+            // converted by hand
+            _ => continue,
+        };
+        if let Some(types) = accepted_types(sql_type) {
+            if !types.contains(&column.field_type.as_str()) {
+                let msg = format!(
+                    "Field '{}' expects column type {}, the schema file has {}",
+                    f.name,
+                    types.join(" or "),
+                    column.field_type
+                );
+                push_error(&mut errors, syn::Error::new_spanned(&f.ty, msg));
+            }
+        }
+        if is_repeated(sql_type) && column.mode != "REPEATED" {
+            let msg = format!(
+                "Field '{}' expects column mode REPEATED, the schema file has {}",
+                f.name, column.mode
+            );
+            push_error(&mut errors, syn::Error::new_spanned(&f.ty, msg));
+        } else if !is_repeated(sql_type) && !is_custom(sql_type) && column.mode == "REPEATED" {
+            // Custom types may be newtypes around a `Vec`
+            let msg = format!(
+                "Field '{}' expects column mode NULLABLE or REQUIRED, the schema file has REPEATED",
+                f.name
+            );
+            push_error(&mut errors, syn::Error::new_spanned(&f.ty, msg));
+        }
+    }
+    if !container_attrs.ignore_extra_columns && !flattened {
+        for (column, claimed) in columns.iter().zip(claimed) {
+            if !claimed {
+                let msg = format!(
+                    "Column '{}' of the schema file isn't read by any field, \
+                     use #[my_bq(ignore_extra_columns)] to accept it",
+                    column.name
+                );
+                push_error(&mut errors, syn::Error::new_spanned(schema, msg));
+            }
+        }
+    }
+    if let Some(errors) = errors {
+        return Err(errors);
+    }
+    let path = path.to_string_lossy();
+    Ok(quote! {
+        const _: &str = include_str!(#path);
+    })
+}
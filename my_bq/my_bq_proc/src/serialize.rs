@@ -0,0 +1,113 @@
+use quote::{quote, ToTokens};
+use syn::DeriveInput;
+
+use crate::column::ColumnType;
+use crate::{bounded_generics, feature_gated, is_custom, parse_fields, FieldKind, SqlType};
+
+// Converts `val: &T` into a `serde_json::Value`
+pub fn value_code(sql_type: &SqlType, ty: &syn::Type) -> proc_macro2::TokenStream {
+    match sql_type {
+        SqlType::String => quote! {::my_bq::serde_json::Value::String(val.clone())},
+        SqlType::Integer | SqlType::Char | SqlType::Numeric | SqlType::Json => {
+            quote! {::my_bq::serde_json::Value::String(val.to_string())}
+        }
+        SqlType::Float => quote! {::my_bq::types::format_float(f64::from(*val))},
+        SqlType::Bool => quote! {::my_bq::serde_json::Value::Bool(*val)},
+        SqlType::Timestamp => {
+            feature_gated(sql_type, ty, quote! {::my_bq::types::format_timestamp(val)})
+        }
+        SqlType::Date => feature_gated(sql_type, ty, quote! {::my_bq::types::format_date(val)}),
+        SqlType::DateTime => {
+            feature_gated(sql_type, ty, quote! {::my_bq::types::format_datetime(val)})
+        }
+        SqlType::Time => feature_gated(sql_type, ty, quote! {::my_bq::types::format_time(val)}),
+        SqlType::Bytes => feature_gated(sql_type, ty, quote! {::my_bq::types::format_bytes(val)}),
+        SqlType::Custom => {
+            quote! {<#ty as ::my_bq::client::ColumnSerialize>::serialize_column(val)}
+        }
+        SqlType::Option(subtype) => {
+            let inner_code = value_code(subtype, ty);
+            quote! {
+                match val {
+                    Some(val) => #inner_code,
+                    None => ::my_bq::serde_json::Value::Null,
+                }
+            }
+        }
+        SqlType::Repeated(subtype) => {
+            let item_code = value_code(subtype, ty);
+            quote! {
+                ::my_bq::serde_json::Value::Array(val.iter().map(|val| #item_code).collect())
+            }
+        }
+    }
+}
+
+// BigQuery rejects arrays holding NULL, so `Vec<Option<T>>` can't be written. `tokens` locates
+// the error
+pub fn check_serializable(sql_type: &SqlType, tokens: impl ToTokens) -> syn::Result<()> {
+    match sql_type {
+        SqlType::Option(subtype) => check_serializable(subtype, tokens),
+        SqlType::Repeated(subtype) if matches!(**subtype, SqlType::Option(_)) => {
+            Err(syn::Error::new_spanned(
+                tokens,
+                "BigQuery arrays can't hold NULL, `Vec<Option<T>>` can't be serialized",
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn expand_serialize(ast: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if let Some(column_type) = ColumnType::parse(&ast)? {
+        return column_type.expand_serialize(&ast);
+    }
+    let (_, fields) = parse_fields(&ast)?;
+    for f in &fields {
+        if let FieldKind::Column { sql_type, .. } = &f.kind {
+            check_serializable(sql_type, &f.ty)?;
+        }
+    }
+    let ident = ast.ident;
+    let fields_code = fields.iter().map(|f| {
+        let field_ident = &f.ident;
+        let name = &f.name;
+        match &f.kind {
+            FieldKind::Column { inner_ty, sql_type } => {
+                let value_code = value_code(sql_type, inner_ty);
+                quote! {
+                    let val = &self.#field_ident;
+                    row.insert(#name.to_string(), #value_code);
+                }
+            }
+            FieldKind::With(path) => quote! {
+                row.insert(#name.to_string(), #path::serialize(&self.#field_ident));
+            },
+            FieldKind::Flatten(ty) => quote! {
+                row.extend(<#ty as ::my_bq::client::Serialize>::serialize(&self.#field_ident));
+            },
+            FieldKind::Skip => quote! {},
+        }
+    });
+    let bounds = fields
+        .iter()
+        .filter_map(|f| match &f.kind {
+            FieldKind::Column { inner_ty, sql_type } if is_custom(sql_type) => {
+                Some((inner_ty.clone(), quote! {::my_bq::client::ColumnSerialize}))
+            }
+            FieldKind::Flatten(ty) => Some((ty.clone(), quote! {::my_bq::client::Serialize})),
+            _ => None,
+        })
+        .collect();
+    let generics = bounded_generics(&ast.generics, bounds);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::my_bq::client::Serialize for #ident #ty_generics #where_clause {
+            fn serialize(&self) -> ::my_bq::serde_json::Map<String, ::my_bq::serde_json::Value> {
+                let mut row = ::my_bq::serde_json::Map::new();
+                #(#fields_code)*
+                row
+            }
+        }
+    })
+}
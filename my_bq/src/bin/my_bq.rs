@@ -0,0 +1,512 @@
+//! Command-line tool for quick checks without the `bq` CLI, built with the `cli` feature:
+//! `my_bq query`, `my_bq jobs ls|cancel`, `my_bq schema` and `my_bq export`.
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+use anyhow::{anyhow, Context};
+use chrono::TimeZone;
+use clap::{Parser, Subcommand, ValueEnum};
+use my_bq::client::{Client, Job};
+use my_bq::structs::row_field::Value;
+use my_bq::structs::table_field_schema::TableFieldSchema;
+use my_bq::TableRow;
+
+#[derive(Parser)]
+#[command(
+    name = "my_bq",
+    about = "Runs BigQuery queries and inspects jobs and tables"
+)]
+struct Cli {
+    /// Project running the jobs, defaults to $GOOGLE_CLOUD_PROJECT
+    #[arg(long, global = true)]
+    project: Option<String>,
+    /// Service account key file, defaults to the application default credentials
+    #[arg(long, global = true)]
+    key_file: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a query and prints the results as a table. `-` reads the query from stdin
+    Query {
+        sql: String,
+        /// Number of rows printed
+        #[arg(long, default_value_t = 100)]
+        max_rows: usize,
+    },
+    /// Lists or cancels jobs
+    Jobs {
+        #[command(subcommand)]
+        command: JobsCommand,
+    },
+    /// Prints the schema of a table, given as `[project.]dataset.table`
+    Schema {
+        table: String,
+        /// Prints the JSON list of fields instead, like `bq show --schema`
+        #[arg(long)]
+        json: bool,
+    },
+    /// Runs a query and writes all the results. `-` reads the query from stdin
+    Export {
+        sql: String,
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        /// Output file, defaults to stdout
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum JobsCommand {
+    /// Lists the most recent jobs started by the current user
+    Ls {
+        #[arg(long, default_value_t = 20)]
+        max_results: usize,
+    },
+    /// Requests the cancellation of a job
+    Cancel {
+        job_id: String,
+        /// Location of the job, required outside of the US and EU multi-regions
+        #[arg(long)]
+        location: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Header row with the column names, records and arrays as JSON
+    Csv,
+    /// One JSON object per row, values as returned by the API
+    Ndjson,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let client = match &cli.key_file {
+        Some(key_file) => Client::from_service_account_key_file(key_file).await?,
+        None => Client::from_application_default_credentials().await?,
+    };
+    let project = cli
+        .project
+        .clone()
+        .or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok());
+    let project = || project.clone().ok_or_else(|| anyhow!("Missing --project"));
+    match cli.command {
+        Command::Query { sql, max_rows } => {
+            let job = post_query(&client, &project()?, &sql).await?;
+            // Only the printed rows are fetched
+            let mut page = job.get_results_page::<TableRow>(0, max_rows).await?;
+            while page.rows.len() < max_rows.min(page.total_rows) {
+                let next = job
+                    .get_results_page::<TableRow>(page.rows.len(), max_rows - page.rows.len())
+                    .await?;
+                if next.rows.is_empty() {
+                    break;
+                }
+                page.rows.extend(next.rows);
+            }
+            let headers: Vec<String> = page.schema.fields.iter().map(|f| f.name.clone()).collect();
+            let cells: Vec<Vec<String>> = page
+                .rows
+                .iter()
+                .map(|row| row_cells(row, &page.schema.fields, "NULL"))
+                .collect();
+            print!("{}", render_table(&headers, &cells));
+            if page.total_rows > page.rows.len() {
+                println!(
+                    "{} rows, showing the first {}",
+                    page.total_rows,
+                    page.rows.len()
+                );
+            } else {
+                println!("{} rows", page.total_rows);
+            }
+        }
+        Command::Jobs {
+            command: JobsCommand::Ls { max_results },
+        } => {
+            let jobs = client.list_jobs(&project()?, max_results).await?;
+            let headers = ["job_id", "state", "created", "statement", "bytes", "query"]
+                .map(String::from)
+                .to_vec();
+            let cells: Vec<Vec<String>> = jobs
+                .iter()
+                .map(|job| {
+                    let state = match job.status() {
+                        Some(status) if status.error_result.is_some() => "FAILED".to_string(),
+                        Some(status) => status
+                            .state
+                            .as_ref()
+                            .map(|state| format!("{:?}", state).to_uppercase())
+                            .unwrap_or_default(),
+                        None => String::new(),
+                    };
+                    let statistics = job.statistics();
+                    let created = statistics
+                        .and_then(|statistics| statistics.creation_time.as_deref())
+                        .map(format_millis)
+                        .unwrap_or_default();
+                    let query_statistics =
+                        statistics.and_then(|statistics| statistics.query.as_ref());
+                    let statement = query_statistics
+                        .and_then(|query| query.statement_type.clone())
+                        .unwrap_or_default();
+                    let bytes = query_statistics
+                        .and_then(|query| query.total_bytes_processed.clone())
+                        .unwrap_or_default();
+                    let query = job.query().map(query_summary).unwrap_or_default();
+                    vec![
+                        job.job_id().unwrap_or_default().to_string(),
+                        state,
+                        created,
+                        statement,
+                        bytes,
+                        query,
+                    ]
+                })
+                .collect();
+            print!("{}", render_table(&headers, &cells));
+        }
+        Command::Jobs {
+            command: JobsCommand::Cancel { job_id, location },
+        } => {
+            let job = client
+                .get_job(&project()?, &job_id, location.as_deref())
+                .await?
+                .cancel()
+                .await?;
+            let state = job
+                .status()
+                .and_then(|status| status.state.as_ref())
+                .map(|state| format!("{:?}", state).to_uppercase())
+                .unwrap_or_default();
+            println!(
+                "Requested the cancellation of job {}, now {}",
+                job_id, state
+            );
+        }
+        Command::Schema { table, json } => {
+            let (project_id, dataset_id, table_id) = parse_table(&table, project().ok())?;
+            let table = client
+                .get_table(&project_id, &dataset_id, &table_id)
+                .await?;
+            let schema = table
+                .schema
+                .ok_or_else(|| anyhow!("Table {} has no schema", table_id))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&schema.fields)?);
+            } else {
+                let headers = ["column", "type", "mode"].map(String::from).to_vec();
+                let mut cells = Vec::new();
+                schema_rows(&schema.fields, "", &mut cells);
+                print!("{}", render_table(&headers, &cells));
+            }
+        }
+        Command::Export {
+            sql,
+            format,
+            output,
+        } => {
+            let job = post_query(&client, &project()?, &sql).await?;
+            let out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(File::create(path).with_context(|| path.clone())?),
+                None => Box::new(io::stdout().lock()),
+            };
+            let mut out = BufWriter::new(out);
+            // Pages are written as they come, the result may not fit in memory
+            let mut start_index = 0;
+            loop {
+                let page = job
+                    .get_results_page::<TableRow>(start_index, EXPORT_PAGE_SIZE)
+                    .await?;
+                let fields = &page.schema.fields;
+                if start_index == 0 {
+                    if let Format::Csv = format {
+                        let headers: Vec<String> = fields.iter().map(|f| f.name.clone()).collect();
+                        writeln!(out, "{}", csv_record(&headers))?;
+                    }
+                }
+                for row in &page.rows {
+                    match format {
+                        Format::Csv => {
+                            writeln!(out, "{}", csv_record(&row_cells(row, fields, "")))?
+                        }
+                        Format::Ndjson => writeln!(out, "{}", row_json(row, fields))?,
+                    }
+                }
+                start_index += page.rows.len();
+                if page.rows.is_empty() || start_index >= page.total_rows {
+                    break;
+                }
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Rows requested per page by `my_bq export`, BigQuery returns fewer when they exceed about 10MB
+const EXPORT_PAGE_SIZE: usize = 10000;
+
+async fn post_query(client: &Client, project_id: &str, sql: &str) -> anyhow::Result<Job> {
+    let sql = if sql == "-" {
+        let mut sql = String::new();
+        io::stdin().read_to_string(&mut sql)?;
+        sql
+    } else {
+        sql.to_string()
+    };
+    Ok(client.post_query(project_id, sql).await?)
+}
+
+// Splits `project.dataset.table` or `project:dataset.table`, the project defaulting to `project`
+fn parse_table(table: &str, project: Option<String>) -> anyhow::Result<(String, String, String)> {
+    let parts: Vec<&str> = table.split(['.', ':']).collect();
+    match parts.as_slice() {
+        [project_id, dataset_id, table_id] => Ok((
+            project_id.to_string(),
+            dataset_id.to_string(),
+            table_id.to_string(),
+        )),
+        [dataset_id, table_id] => Ok((
+            project.ok_or_else(|| anyhow!("Missing --project for table {}", table))?,
+            dataset_id.to_string(),
+            table_id.to_string(),
+        )),
+        _ => Err(anyhow!(
+            "Expected a table as [project.]dataset.table, got {}",
+            table
+        )),
+    }
+}
+
+// The value as returned by the API in JSON, records as objects keyed by column name. The JSON is
+// written by hand to keep the columns in schema order, `serde_json::Map` sorts its keys.
+fn value_json(value: Option<&Value>, field: &TableFieldSchema) -> String {
+    match value {
+        Some(Value::String(val)) => serde_json::Value::from(val.as_str()).to_string(),
+        Some(Value::Array(items)) => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| value_json(item.value.as_ref(), field))
+                .collect();
+            format!("[{}]", items.join(","))
+        }
+        Some(Value::Record(row)) => row_json(row, field.fields.as_deref().unwrap_or_default()),
+        Some(Value::Unknown) | None => "null".to_string(),
+    }
+}
+
+fn row_json(row: &TableRow, fields: &[TableFieldSchema]) -> String {
+    let members: Vec<String> = fields
+        .iter()
+        .zip(&row.fields)
+        .map(|(field, value)| {
+            let name = serde_json::Value::from(field.name.as_str());
+            format!("{}:{}", name, value_json(value.value.as_ref(), field))
+        })
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+// Scalars as is, records and arrays as JSON
+fn row_cells(row: &TableRow, fields: &[TableFieldSchema], null: &str) -> Vec<String> {
+    fields
+        .iter()
+        .zip(&row.fields)
+        .map(|(field, value)| match &value.value {
+            Some(Value::String(val)) => val.clone(),
+            Some(Value::Unknown) | None => null.to_string(),
+            value => value_json(value.as_ref(), field),
+        })
+        .collect()
+}
+
+fn schema_rows(fields: &[TableFieldSchema], prefix: &str, rows: &mut Vec<Vec<String>>) {
+    for field in fields {
+        let path = format!("{}{}", prefix, field.name);
+        rows.push(vec![
+            path.clone(),
+            format!("{:?}", field.field_type).to_uppercase(),
+            format!("{:?}", field.mode).to_uppercase(),
+        ]);
+        if let Some(fields) = &field.fields {
+            schema_rows(fields, &format!("{}.", path), rows);
+        }
+    }
+}
+
+// Milliseconds since the epoch as UTC time
+fn format_millis(millis: &str) -> String {
+    millis
+        .parse::<i64>()
+        .ok()
+        .and_then(|millis| chrono::Utc.timestamp_millis_opt(millis).single())
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| millis.to_string())
+}
+
+// First line of the query, shortened to fit a table cell
+fn query_summary(query: &str) -> String {
+    let line = query.trim().lines().next().unwrap_or_default();
+    if line.chars().count() > 60 {
+        format!("{}...", line.chars().take(57).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+// Rows aligned in columns framed by `+---+` borders, like the output of the bq CLI
+fn render_table(headers: &[String], rows: &[Vec<String>]) -> String {
+    // Newlines would break the frame
+    let clean = |cell: &String| cell.replace(['\n', '\r'], " ");
+    let headers: Vec<String> = headers.iter().map(clean).collect();
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(clean).collect())
+        .collect();
+    let mut widths: Vec<usize> = headers.iter().map(|cell| cell.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let border: String = widths
+        .iter()
+        .map(|width| format!("+{}", "-".repeat(width + 2)))
+        .collect::<String>()
+        + "+\n";
+    let line = |cells: &[String]| {
+        widths
+            .iter()
+            .zip(cells)
+            .map(|(width, cell)| format!("| {}{} ", cell, " ".repeat(width - cell.chars().count())))
+            .collect::<String>()
+            + "|\n"
+    };
+    let mut table = border.clone();
+    table.push_str(&line(&headers));
+    table.push_str(&border);
+    for row in &rows {
+        table.push_str(&line(row));
+    }
+    if !rows.is_empty() {
+        table.push_str(&border);
+    }
+    table
+}
+
+// RFC 4180 record, quoting cells holding separators, quotes or newlines
+fn csv_record(cells: &[String]) -> String {
+    cells
+        .iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use my_bq::structs::table_schema::TableSchema;
+
+    fn schema() -> TableSchema {
+        serde_json::from_str(
+            r#"{"fields": [
+                {"name": "name", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "tags", "type": "STRING", "mode": "REPEATED"},
+                {"name": "device", "type": "RECORD", "mode": "NULLABLE", "fields": [
+                    {"name": "category", "type": "STRING", "mode": "NULLABLE"},
+                    {"name": "brand", "type": "STRING", "mode": "NULLABLE"}
+                ]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn row() -> TableRow {
+        serde_json::from_str(
+            r#"{"f": [
+                {"v": "a, \"b\""},
+                {"v": [{"v": "x"}, {"v": "y"}]},
+                {"v": {"f": [{"v": "mobile"}, {"v": null}]}}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_row_output() {
+        let fields = schema().fields;
+        // Columns in schema order
+        assert_eq!(
+            row_json(&row(), &fields),
+            r#"{"name":"a, \"b\"","tags":["x","y"],"device":{"category":"mobile","brand":null}}"#
+        );
+        let cells = row_cells(&row(), &fields, "");
+        assert_eq!(
+            csv_record(&cells),
+            r#""a, ""b""","[""x"",""y""]","{""category"":""mobile"",""brand"":null}""#
+        );
+        let null_row: TableRow =
+            serde_json::from_str(r#"{"f": [{"v": null}, {"v": []}, {"v": null}]}"#).unwrap();
+        assert_eq!(
+            row_cells(&null_row, &fields, "NULL"),
+            ["NULL", "[]", "NULL"]
+        );
+    }
+
+    #[test]
+    fn test_render_table() {
+        let headers = vec!["name".to_string(), "n".to_string()];
+        let rows = vec![
+            vec!["ab".to_string(), "1".to_string()],
+            vec!["é\nz".to_string(), "12345".to_string()],
+        ];
+        assert_eq!(
+            render_table(&headers, &rows),
+            "+------+-------+\n\
+             | name | n     |\n\
+             +------+-------+\n\
+             | ab   | 1     |\n\
+             | é z  | 12345 |\n\
+             +------+-------+\n"
+        );
+    }
+
+    #[test]
+    fn test_schema_rows() {
+        let mut rows = Vec::new();
+        schema_rows(&schema().fields, "", &mut rows);
+        assert_eq!(rows[3], ["device.category", "STRING", "NULLABLE"]);
+        assert_eq!(rows[1], ["tags", "STRING", "REPEATED"]);
+    }
+
+    #[test]
+    fn test_parse_table() {
+        assert_eq!(
+            parse_table("p:d.t", None).unwrap(),
+            ("p".to_string(), "d".to_string(), "t".to_string())
+        );
+        assert_eq!(parse_table("d.t", Some("p".to_string())).unwrap().0, "p");
+        assert!(parse_table("d.t", None).is_err());
+        assert!(parse_table("t", Some("p".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_format_millis() {
+        assert_eq!(format_millis("1666051200000"), "2022-10-18 00:00:00");
+        assert_eq!(format_millis("soon"), "soon");
+        assert_eq!(query_summary("\n  SELECT 1\nFROM t"), "SELECT 1");
+    }
+}
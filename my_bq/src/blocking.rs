@@ -0,0 +1,357 @@
+//! A blocking facade over [`crate::client`], for synchronous programs.
+//!
+//! Every client owns a tokio runtime and drives the async API to completion on it,
+//! similar to `reqwest::blocking`. As with `reqwest::blocking`, these methods must not be
+//! called from within an async runtime, otherwise they will panic.
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+
+use crate::client::{self, Deserialize, ResultsPage};
+use crate::error::BigQueryError;
+use crate::metrics::Metrics;
+use crate::structs::job_statistics::JobStatistics;
+use crate::structs::job_status::JobStatus;
+use crate::structs::table::Table;
+use crate::structs::table_schema::TableSchema;
+
+pub struct Client {
+    inner: client::Client,
+    runtime: Arc<OwnedRuntime>,
+}
+
+// The runtime shared by a client and its sessions and jobs. Dropping a tokio runtime panics within
+// an async context, so the last of them is shut down in the background instead, without waiting
+// for the tasks left.
+#[derive(Debug)]
+struct OwnedRuntime(Option<Runtime>);
+
+impl OwnedRuntime {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.0.as_ref().unwrap().block_on(future)
+    }
+}
+
+impl Drop for OwnedRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+fn build_runtime() -> Result<Arc<OwnedRuntime>, BigQueryError> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(BigQueryError::RuntimeCreationError)?;
+    Ok(Arc::new(OwnedRuntime(Some(runtime))))
+}
+
+impl Client {
+    /// See [`client::Client::new`].
+    pub fn new(secret_path: &str) -> Self {
+        Self::try_new(secret_path).expect("failed to create authenticator")
+    }
+    /// See [`client::Client::try_new`].
+    pub fn try_new(secret_path: &str) -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::try_new(secret_path))?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::from_service_account_key_file`].
+    pub fn from_service_account_key_file(key_path: &str) -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::from_service_account_key_file(key_path))?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::from_service_account_key`].
+    pub fn from_service_account_key(
+        key: yup_oauth2::ServiceAccountKey,
+    ) -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::from_service_account_key(key))?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::from_application_credentials_env`].
+    pub fn from_application_credentials_env() -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::from_application_credentials_env())?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::from_metadata_server`].
+    pub fn from_metadata_server() -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::from_metadata_server())?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::from_application_default_credentials`].
+    pub fn from_application_default_credentials() -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = runtime.block_on(client::Client::from_application_default_credentials())?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::with_metrics`].
+    pub fn with_metrics(self, metrics: Arc<dyn Metrics>) -> Self {
+        Client {
+            inner: self.inner.with_metrics(metrics),
+            runtime: self.runtime,
+        }
+    }
+    /// See [`client::Client::replay_from`].
+    pub fn replay_from(dir: impl AsRef<Path>) -> Result<Self, BigQueryError> {
+        let runtime = build_runtime()?;
+        let inner = client::Client::replay_from(dir)?;
+        Ok(Client { inner, runtime })
+    }
+    /// See [`client::Client::record_to`].
+    pub fn record_to(self, dir: impl AsRef<Path>) -> Result<Self, BigQueryError> {
+        Ok(Client {
+            inner: self.inner.record_to(dir)?,
+            runtime: self.runtime,
+        })
+    }
+    /// See [`client::Client::post_query`].
+    pub fn post_query(&self, project_id: &str, query: String) -> Result<Job, BigQueryError> {
+        let inner = self
+            .runtime
+            .block_on(self.inner.post_query(project_id, query))?;
+        Ok(Job {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+    /// See [`client::Client::list_jobs`].
+    pub fn list_jobs(
+        &self,
+        project_id: &str,
+        max_results: usize,
+    ) -> Result<Vec<Job>, BigQueryError> {
+        let jobs = self
+            .runtime
+            .block_on(self.inner.list_jobs(project_id, max_results))?;
+        Ok(jobs
+            .into_iter()
+            .map(|inner| Job {
+                inner,
+                runtime: self.runtime.clone(),
+            })
+            .collect())
+    }
+    /// See [`client::Client::get_job`].
+    pub fn get_job(
+        &self,
+        project_id: &str,
+        job_id: &str,
+        location: Option<&str>,
+    ) -> Result<Job, BigQueryError> {
+        let inner = self
+            .runtime
+            .block_on(self.inner.get_job(project_id, job_id, location))?;
+        Ok(Job {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+    /// See [`client::Client::get_table`].
+    pub fn get_table(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+    ) -> Result<Table, BigQueryError> {
+        self.runtime
+            .block_on(self.inner.get_table(project_id, dataset_id, table_id))
+    }
+    /// See [`client::Client::create_session`].
+    pub fn create_session(&self, project_id: &str) -> Result<Session, BigQueryError> {
+        let inner = self
+            .runtime
+            .block_on(self.inner.create_session(project_id))?;
+        Ok(Session {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    inner: client::Session,
+    runtime: Arc<OwnedRuntime>,
+}
+
+impl Session {
+    pub fn session_id(&self) -> &str {
+        self.inner.session_id()
+    }
+    /// See [`client::Session::post_query`].
+    pub fn post_query(&self, query: String) -> Result<Job, BigQueryError> {
+        let inner = self.runtime.block_on(self.inner.post_query(query))?;
+        Ok(Job {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+    pub fn begin_transaction(&self) -> Result<(), BigQueryError> {
+        self.runtime.block_on(self.inner.begin_transaction())
+    }
+    pub fn commit(&self) -> Result<(), BigQueryError> {
+        self.runtime.block_on(self.inner.commit())
+    }
+    pub fn rollback(&self) -> Result<(), BigQueryError> {
+        self.runtime.block_on(self.inner.rollback())
+    }
+    /// See [`client::Session::abort`].
+    pub fn abort(self) -> Result<(), BigQueryError> {
+        self.runtime.block_on(self.inner.abort())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Job {
+    inner: client::Job,
+    runtime: Arc<OwnedRuntime>,
+}
+
+impl Job {
+    pub fn job_id(&self) -> Option<&str> {
+        self.inner.job_id()
+    }
+    /// See [`client::Job::status`].
+    pub fn status(&self) -> Option<&JobStatus> {
+        self.inner.status()
+    }
+    /// See [`client::Job::query`].
+    pub fn query(&self) -> Option<&str> {
+        self.inner.query()
+    }
+    /// See [`client::Job::statistics`].
+    pub fn statistics(&self) -> Option<&JobStatistics> {
+        self.inner.statistics()
+    }
+    /// See [`client::Job::wait`].
+    pub fn wait(&self) -> Result<Job, BigQueryError> {
+        let inner = self.runtime.block_on(self.inner.wait())?;
+        Ok(Job {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+    /// See [`client::Job::cancel`].
+    pub fn cancel(&self) -> Result<Job, BigQueryError> {
+        let inner = self.runtime.block_on(self.inner.cancel())?;
+        Ok(Job {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+    /// See [`client::Job::child_jobs`].
+    pub fn child_jobs(&self) -> Result<Vec<Job>, BigQueryError> {
+        let children = self.runtime.block_on(self.inner.child_jobs())?;
+        Ok(children
+            .into_iter()
+            .map(|inner| Job {
+                inner,
+                runtime: self.runtime.clone(),
+            })
+            .collect())
+    }
+    /// See [`client::Job::with_cancel_on_abort`].
+    pub fn with_cancel_on_abort(self) -> Self {
+        Job {
+            inner: self.inner.with_cancel_on_abort(),
+            runtime: self.runtime,
+        }
+    }
+    /// See [`client::Job::get_results`].
+    pub fn get_results<T>(&self) -> Result<Vec<T>, BigQueryError>
+    where
+        T: Deserialize + Send + 'static,
+    {
+        self.runtime.block_on(self.inner.get_results())
+    }
+    /// See [`client::Job::get_results_with_schema`].
+    pub fn get_results_with_schema<T>(&self) -> Result<(TableSchema, Vec<T>), BigQueryError>
+    where
+        T: Deserialize + Send + 'static,
+    {
+        self.runtime.block_on(self.inner.get_results_with_schema())
+    }
+    /// See [`client::Job::get_results_page`].
+    pub fn get_results_page<T: Deserialize>(
+        &self,
+        start_index: usize,
+        max_results: usize,
+    ) -> Result<ResultsPage<T>, BigQueryError> {
+        self.runtime
+            .block_on(self.inner.get_results_page(start_index, max_results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::row_field::Value;
+    use crate::TableRow;
+
+    #[test]
+    fn test_missing_credentials_file() {
+        let res = Client::try_new("/nonexistent/authorized_user.json");
+        assert!(matches!(
+            res,
+            Err(BigQueryError::AuthenticatorSetupError(_))
+        ));
+    }
+
+    fn replay_client(name: &str) -> Client {
+        use crate::fixtures::testing::*;
+        let dir = write_fixtures(
+            name,
+            &[
+                insert_job("job_1"),
+                results("job_1", None, 2, &["a"], false),
+                results("job_1", Some("maxResults=1&startIndex=1"), 2, &["b"], true),
+            ],
+        );
+        let client = Client::replay_from(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        client
+    }
+
+    #[test]
+    fn test_get_results() {
+        let client = replay_client("blocking_get_results");
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .unwrap();
+        assert_eq!(job.job_id(), Some("job_1"));
+        let (schema, rows) = job.get_results_with_schema::<TableRow>().unwrap();
+        assert_eq!(schema.fields[0].name, "name");
+        let names: Vec<_> = rows
+            .into_iter()
+            .map(|row| row.fields[0].value.clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Some(Value::String("a".to_string())),
+                Some(Value::String("b".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drop_in_async_context() {
+        let client = replay_client("blocking_drop");
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .unwrap();
+        drop(client);
+        // Dropping the last handle on the runtime must not panic
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move { drop(job) });
+    }
+}
@@ -1,84 +1,527 @@
 use std::cmp::min;
+use std::ffi::OsStr;
 use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::error::BigQueryError;
+use crate::error::{BigQueryError, FieldMismatch, SchemaMismatch};
+use crate::fixtures::Fixtures;
+use crate::metrics::{self, Metrics};
+use crate::query::SelectColumn;
 use crate::structs;
+use crate::structs::connection_property::ConnectionProperty;
 use crate::structs::error_proto::ErrorProto;
-use crate::structs::job_query_results::JobQueryResults;
-use crate::structs::job_status::JobStatus;
-use crate::structs::table_field_schema::TableFieldSchema;
-use log::debug;
+use crate::structs::error_response::ErrorResponse;
+use crate::structs::job_cancel_response::JobCancelResponse;
+use crate::structs::job_list::JobList;
+use crate::structs::job_query_results::RawQueryResults;
+use crate::structs::job_statistics::JobStatistics;
+use crate::structs::job_status::{JobStatus, State};
+use crate::structs::raw_row::{RawRow, RawValue};
+use crate::structs::row_field::Value;
+use crate::structs::table::Table;
+use crate::structs::table_field_schema::{Mode, TableFieldSchema, Type};
+use crate::structs::table_schema::TableSchema;
 use structs::table_row::TableRow;
 use tokio::sync::Semaphore;
 use tokio::task;
 use tokio::time::Duration;
-use yup_oauth2::authenticator::DefaultAuthenticator;
+use tracing::{debug, field, Instrument};
+use yup_oauth2::authenticator::{ApplicationDefaultCredentialsTypes, DefaultAuthenticator};
+use yup_oauth2::storage::TokenInfo;
 
 #[derive(Clone)]
 struct InnerClient {
-    authenticator: DefaultAuthenticator,
+    // None when replaying fixtures
+    authenticator: Option<DefaultAuthenticator>,
     reqwest_client: reqwest::Client,
+    metrics: Option<Arc<dyn Metrics>>,
+    fixtures: Option<Arc<Fixtures>>,
+}
+
+impl InnerClient {
+    fn increment_counter(&self, name: &'static str, value: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment_counter(name, value);
+        }
+    }
+    fn record_histogram(&self, name: &'static str, value: f64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_histogram(name, value);
+        }
+    }
+    fn record_latency(&self, name: &'static str, started: Instant) -> u128 {
+        let latency = started.elapsed();
+        self.record_histogram(name, latency.as_secs_f64());
+        latency.as_millis()
+    }
+    async fn token(&self) -> Result<yup_oauth2::AccessToken, BigQueryError> {
+        match &self.authenticator {
+            Some(authenticator) => Ok(authenticator.token(SCOPES).await?),
+            None => Ok(TokenInfo {
+                access_token: String::new(),
+                refresh_token: None,
+                expires_at: None,
+                id_token: None,
+            }
+            .into()),
+        }
+    }
+    // Every API call goes through here, to be recorded or replayed
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, BigQueryError> {
+        let request = request.build()?;
+        match &self.fixtures {
+            Some(fixtures) => fixtures.send(&self.reqwest_client, request).await,
+            None => Ok(self.reqwest_client.execute(request).await?),
+        }
+    }
+    async fn get_with_retry(
+        &self,
+        api_url: &str,
+        tok: &yup_oauth2::AccessToken,
+    ) -> Result<reqwest::Response, BigQueryError> {
+        self.get_with_retry_query(api_url, &[], tok).await
+    }
+    // Same as `get_with_retry`, adding the url-encoded `query` parameters to `api_url`.
+    // Records the number of retries in the current span, under the `retries` field
+    async fn get_with_retry_query(
+        &self,
+        api_url: &str,
+        query: &[(&str, &str)],
+        tok: &yup_oauth2::AccessToken,
+    ) -> Result<reqwest::Response, BigQueryError> {
+        let attempts = AtomicUsize::new(0);
+        let res = again::retry_if(
+            || {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                self.send(
+                    self.reqwest_client
+                        .get(api_url)
+                        .query(query)
+                        .bearer_auth(tok.as_str()),
+                )
+            },
+            |err: &BigQueryError| {
+                // we want to retry hyper::Error(IncompleteMessage), which seems to happen rarely during https requests
+                // https://github.com/hyperium/hyper/issues/2136
+                matches!(err, BigQueryError::ApiRequestError(err) if err.is_request() || err.is_body())
+            },
+        )
+        .await;
+        let retries = attempts.load(Ordering::Relaxed).saturating_sub(1);
+        tracing::Span::current().record("retries", retries);
+        if retries > 0 {
+            self.increment_counter(metrics::REQUEST_RETRIES, retries as u64);
+        }
+        res
+    }
 }
 pub struct Client {
     inner_client: Arc<InnerClient>,
 }
 
 const SCOPES: &[&str; 1] = &["https://www.googleapis.com/auth/bigquery"];
+const CREDENTIALS_ENV_VAR: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+// Key file path held by `GOOGLE_APPLICATION_CREDENTIALS`, given its value
+fn credentials_key_path(env_value: Option<String>) -> Result<String, BigQueryError> {
+    env_value.ok_or(BigQueryError::MissingCredentialsEnvVar {
+        var: CREDENTIALS_ENV_VAR,
+    })
+}
+
+// Fails when `GOOGLE_APPLICATION_CREDENTIALS` has a value, yup-oauth2 preferring the key file to
+// the metadata server
+fn check_credentials_env_var_unset(env_value: Option<&OsStr>) -> Result<(), BigQueryError> {
+    match env_value {
+        Some(_) => Err(BigQueryError::UnexpectedCredentialsEnvVar {
+            var: CREDENTIALS_ENV_VAR,
+        }),
+        None => Ok(()),
+    }
+}
 
 impl Client {
+    /// Creates a client from an authorized user secret, panicking on failure.
+    /// See [`Client::try_new`] for the fallible version.
     pub async fn new(secret_path: &str) -> Self {
+        Self::try_new(secret_path)
+            .await
+            .expect("failed to create authenticator")
+    }
+    /// Creates a client from an authorized user secret (as produced by `gcloud auth application-default login`).
+    pub async fn try_new(secret_path: &str) -> Result<Self, BigQueryError> {
         let secret = yup_oauth2::read_authorized_user_secret(secret_path)
             .await
-            .unwrap();
+            .map_err(BigQueryError::AuthenticatorSetupError)?;
         let authenticator = yup_oauth2::AuthorizedUserAuthenticator::builder(secret)
             .build()
             .await
-            .expect("failed to create authenticator");
+            .map_err(BigQueryError::AuthenticatorSetupError)?;
+        Ok(Self::from_authenticator(authenticator))
+    }
+    /// Creates a client from a service account JSON key file.
+    pub async fn from_service_account_key_file(key_path: &str) -> Result<Self, BigQueryError> {
+        let key = yup_oauth2::read_service_account_key(key_path)
+            .await
+            .map_err(BigQueryError::AuthenticatorSetupError)?;
+        Self::from_service_account_key(key).await
+    }
+    /// Creates a client from an already parsed service account key.
+    pub async fn from_service_account_key(
+        key: yup_oauth2::ServiceAccountKey,
+    ) -> Result<Self, BigQueryError> {
+        let authenticator = yup_oauth2::ServiceAccountAuthenticator::builder(key)
+            .build()
+            .await
+            .map_err(BigQueryError::AuthenticatorSetupError)?;
+        Ok(Self::from_authenticator(authenticator))
+    }
+    /// Creates a client from the service account key file pointed to by `GOOGLE_APPLICATION_CREDENTIALS`.
+    pub async fn from_application_credentials_env() -> Result<Self, BigQueryError> {
+        let key_path = credentials_key_path(std::env::var(CREDENTIALS_ENV_VAR).ok())?;
+        Self::from_service_account_key_file(&key_path).await
+    }
+    /// Creates a client that fetches tokens from the GCE/GKE metadata server (workload identity).
+    /// Fails if `GOOGLE_APPLICATION_CREDENTIALS` is set, since yup-oauth2 would prefer the key file.
+    pub async fn from_metadata_server() -> Result<Self, BigQueryError> {
+        check_credentials_env_var_unset(std::env::var_os(CREDENTIALS_ENV_VAR).as_deref())?;
+        let opts = yup_oauth2::ApplicationDefaultCredentialsFlowOpts::default();
+        let authenticator =
+            match yup_oauth2::ApplicationDefaultCredentialsAuthenticator::builder(opts).await {
+                ApplicationDefaultCredentialsTypes::InstanceMetadata(auth) => auth
+                    .build()
+                    .await
+                    .map_err(BigQueryError::AuthenticatorSetupError)?,
+                ApplicationDefaultCredentialsTypes::ServiceAccount(_) => {
+                    return Err(BigQueryError::UnexpectedCredentialsEnvVar {
+                        var: CREDENTIALS_ENV_VAR,
+                    })
+                }
+            };
+        Ok(Self::from_authenticator(authenticator))
+    }
+    /// Creates a client using application default credentials:
+    /// `GOOGLE_APPLICATION_CREDENTIALS` if it is set, the metadata server otherwise.
+    pub async fn from_application_default_credentials() -> Result<Self, BigQueryError> {
+        let opts = yup_oauth2::ApplicationDefaultCredentialsFlowOpts::default();
+        let authenticator =
+            match yup_oauth2::ApplicationDefaultCredentialsAuthenticator::builder(opts).await {
+                ApplicationDefaultCredentialsTypes::ServiceAccount(auth) => auth
+                    .build()
+                    .await
+                    .map_err(BigQueryError::AuthenticatorSetupError)?,
+                ApplicationDefaultCredentialsTypes::InstanceMetadata(auth) => auth
+                    .build()
+                    .await
+                    .map_err(BigQueryError::AuthenticatorSetupError)?,
+            };
+        Ok(Self::from_authenticator(authenticator))
+    }
+    fn from_authenticator(authenticator: DefaultAuthenticator) -> Self {
         Client {
             inner_client: Arc::new(InnerClient {
-                authenticator,
+                authenticator: Some(authenticator),
                 reqwest_client: reqwest::Client::new(),
+                metrics: None,
+                fixtures: None,
             }),
         }
     }
+    /// Creates a client answering every API call from the fixtures recorded in `dir` by
+    /// [`Client::record_to`], without network access or credentials. Calls without a recorded
+    /// response fail with [`BigQueryError::MissingFixture`]. See [`crate::fixtures`].
+    pub fn replay_from(dir: impl AsRef<Path>) -> Result<Self, BigQueryError> {
+        Ok(Client {
+            inner_client: Arc::new(InnerClient {
+                authenticator: None,
+                reqwest_client: reqwest::Client::new(),
+                metrics: None,
+                fixtures: Some(Arc::new(Fixtures::replay(dir.as_ref())?)),
+            }),
+        })
+    }
+    /// Saves every API call and its response to `dir`, created if missing, for
+    /// [`Client::replay_from`]. Fails if `dir` already holds fixtures. See [`crate::fixtures`].
+    pub fn record_to(mut self, dir: impl AsRef<Path>) -> Result<Self, BigQueryError> {
+        Arc::make_mut(&mut self.inner_client).fixtures =
+            Some(Arc::new(Fixtures::record(dir.as_ref())?));
+        Ok(self)
+    }
+    // The fixtures the client records to or replays from
+    #[cfg(test)]
+    pub(crate) fn fixtures(&self) -> Option<&Fixtures> {
+        self.inner_client.fixtures.as_deref()
+    }
+    /// Reports latencies, byte counts and retries of every API call to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        Arc::make_mut(&mut self.inner_client).metrics = Some(metrics);
+        self
+    }
     pub async fn post_query(&self, project_id: &str, query: String) -> Result<Job, BigQueryError> {
+        insert_job(
+            &self.inner_client,
+            project_id,
+            structs::job::Job::new(query),
+        )
+        .await
+    }
+    /// Lists the jobs of the project started by the current user, most recent first, up to
+    /// `max_results`.
+    #[tracing::instrument(name = "bigquery.list_jobs", skip(self), fields(retries = field::Empty))]
+    pub async fn list_jobs(
+        &self,
+        project_id: &str,
+        max_results: usize,
+    ) -> Result<Vec<Job>, BigQueryError> {
+        let tok = self.inner_client.token().await?;
+        let mut jobs = Vec::new();
+        let mut page_token: Option<String> = None;
         let api_url = format!(
             "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/jobs",
-            project_id = project_id
+            project_id = project_id,
+        );
+        while jobs.len() < max_results {
+            let page_size = (max_results - jobs.len()).to_string();
+            let mut query = vec![("projection", "full"), ("maxResults", page_size.as_str())];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+            let res = self
+                .inner_client
+                .get_with_retry_query(&api_url, &query, &tok)
+                .await?;
+            let job_list: JobList = res.error_for_status()?.json().await?;
+            jobs.extend(job_list.jobs);
+            page_token = job_list.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        jobs.truncate(max_results);
+        Ok(jobs
+            .into_iter()
+            .map(|inner_job| Job {
+                inner_client: self.inner_client.clone(),
+                inner_job,
+                project_id: project_id.into(),
+                cancel_on_abort: false,
+            })
+            .collect())
+    }
+    /// Fetches a job of the project by id, to wait for it, read its results or cancel it.
+    /// `location` is required for jobs outside of the US and EU multi-regions.
+    #[tracing::instrument(name = "bigquery.get_job", skip(self), fields(retries = field::Empty))]
+    pub async fn get_job(
+        &self,
+        project_id: &str,
+        job_id: &str,
+        location: Option<&str>,
+    ) -> Result<Job, BigQueryError> {
+        let api_url = format!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/jobs/{job_id}",
+            project_id = project_id,
+            job_id = job_id,
         );
-        let tok = self.inner_client.authenticator.token(SCOPES).await?;
-        let job = structs::job::Job::new(query);
+        let tok = self.inner_client.token().await?;
         let res = self
             .inner_client
-            .reqwest_client
-            .post(api_url)
-            .json(&job)
-            .bearer_auth(tok.as_str())
-            .send()
+            .get_with_retry_query(&api_url, &location_query(location), &tok)
             .await?;
-        let job: structs::job::Job = res.json().await?;
+        let inner_job: structs::job::Job = res.error_for_status()?.json().await?;
+        Ok(Job {
+            inner_client: self.inner_client.clone(),
+            inner_job,
+            project_id: project_id.into(),
+            cancel_on_abort: false,
+        })
+    }
+    /// Fetches the metadata and schema of a table.
+    #[tracing::instrument(name = "bigquery.get_table", skip(self), fields(retries = field::Empty))]
+    pub async fn get_table(
+        &self,
+        project_id: &str,
+        dataset_id: &str,
+        table_id: &str,
+    ) -> Result<Table, BigQueryError> {
+        let api_url = format!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/datasets/{dataset_id}/tables/{table_id}",
+            project_id = project_id,
+            dataset_id = dataset_id,
+            table_id = table_id,
+        );
+        let tok = self.inner_client.token().await?;
+        let res = self.inner_client.get_with_retry(&api_url, &tok).await?;
+        Ok(res.error_for_status()?.json().await?)
+    }
+    /// Creates a BigQuery session. Temp tables and variables created by queries
+    /// posted through the returned [`Session`] persist across those queries.
+    pub async fn create_session(&self, project_id: &str) -> Result<Session, BigQueryError> {
+        let mut job = structs::job::Job::new("SELECT 1".into());
+        if let Some(query) = job
+            .configuration
+            .as_mut()
+            .and_then(|configuration| configuration.query.as_mut())
+        {
+            query.create_session = Some(true);
+        }
+        let job = insert_job(&self.inner_client, project_id, job)
+            .await?
+            .wait()
+            .await?;
+        let session_id = job
+            .statistics()
+            .and_then(|statistics| statistics.session_info.as_ref())
+            .map(|session_info| session_info.session_id.clone())
+            .ok_or(BigQueryError::MissingSessionIdInGoogleApiResponse)?;
+        Ok(Session {
+            inner_client: self.inner_client.clone(),
+            project_id: project_id.into(),
+            session_id,
+        })
+    }
+}
+
+#[tracing::instrument(
+    name = "bigquery.job_insert",
+    skip(inner_client, job),
+    fields(job_id = field::Empty, latency_ms = field::Empty)
+)]
+async fn insert_job(
+    inner_client: &Arc<InnerClient>,
+    project_id: &str,
+    job: structs::job::Job,
+) -> Result<Job, BigQueryError> {
+    let started = Instant::now();
+    let api_url = format!(
+        "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/jobs",
+        project_id = project_id
+    );
+    let tok = inner_client.token().await?;
+    let res = inner_client
+        .send(
+            inner_client
+                .reqwest_client
+                .post(api_url)
+                .json(&job)
+                .bearer_auth(tok.as_str()),
+        )
+        .await?;
+    // Rejected jobs, like queries with syntax errors, come with the reason in the error body
+    if let Some(err) = res.error_for_status_ref().err() {
+        return Err(match res.json::<ErrorResponse>().await {
+            Ok(response) => BigQueryError::JobInsertError {
+                msg: response.error.message,
+            },
+            Err(_) => err.into(),
+        });
+    }
+    let job: structs::job::Job = res.json().await?;
+    let span = tracing::Span::current();
+    span.record(
+        "latency_ms",
+        inner_client.record_latency(metrics::JOB_INSERT_LATENCY, started),
+    );
+    if let Some(job_id) = job.job_reference.as_ref().and_then(|r| r.job_id.as_ref()) {
+        span.record("job_id", job_id.as_str());
+    }
+    if let Some(JobStatus {
+        error_result: Some(ErrorProto { message, .. }),
+        ..
+    }) = job.status
+    {
+        Err(BigQueryError::JobInsertError { msg: message })
+    } else {
         if let Some(JobStatus {
-            error_result: Some(ErrorProto { message, .. }),
+            errors: Some(errors),
             ..
-        }) = job.status
+        }) = &job.status
         {
-            return Err(BigQueryError::JobInsertError { msg: message });
-        } else {
-            if let Some(JobStatus {
-                errors: Some(errors),
-                ..
-            }) = &job.status
-            {
-                for error in errors {
-                    println!("Got error in job insert request: {}", error.message);
-                }
+            for error in errors {
+                tracing::warn!(target: "bigquery_client", "Got error in job insert request: {}", error.message);
             }
-            Ok(Job {
-                inner_job: job,
-                inner_client: self.inner_client.clone(),
-                project_id: project_id.into(),
-            })
+        }
+        Ok(Job {
+            inner_job: job,
+            inner_client: inner_client.clone(),
+            project_id: project_id.into(),
+            cancel_on_abort: false,
+        })
+    }
+}
+
+/// A BigQuery session, see [`Client::create_session`].
+#[derive(Clone)]
+pub struct Session {
+    inner_client: Arc<InnerClient>,
+    project_id: String,
+    session_id: String,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("project_id", &self.project_id)
+            .field("session_id", &self.session_id)
+            .finish()
+    }
+}
+
+impl Session {
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+    /// Same as [`Client::post_query`], but runs the query within this session.
+    pub async fn post_query(&self, query: String) -> Result<Job, BigQueryError> {
+        let mut job = structs::job::Job::new(query);
+        if let Some(query) = job
+            .configuration
+            .as_mut()
+            .and_then(|configuration| configuration.query.as_mut())
+        {
+            query.connection_properties = Some(vec![ConnectionProperty {
+                key: "session_id".into(),
+                value: self.session_id.clone(),
+            }]);
+        }
+        insert_job(&self.inner_client, &self.project_id, job).await
+    }
+    pub async fn begin_transaction(&self) -> Result<(), BigQueryError> {
+        self.run_transaction_statement("BEGIN TRANSACTION").await
+    }
+    pub async fn commit(&self) -> Result<(), BigQueryError> {
+        self.run_transaction_statement("COMMIT TRANSACTION").await
+    }
+    pub async fn rollback(&self) -> Result<(), BigQueryError> {
+        self.run_transaction_statement("ROLLBACK TRANSACTION").await
+    }
+    /// Terminates the session, dropping its temp tables and rolling back an open transaction.
+    pub async fn abort(self) -> Result<(), BigQueryError> {
+        self.post_query("CALL BQ.ABORT_SESSION()".into())
+            .await?
+            .wait()
+            .await?;
+        Ok(())
+    }
+    async fn run_transaction_statement(
+        &self,
+        statement: &'static str,
+    ) -> Result<(), BigQueryError> {
+        let res = match self.post_query(statement.into()).await {
+            Ok(job) => job.wait().await,
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(_) => Ok(()),
+            Err(BigQueryError::JobInsertError { msg }) | Err(BigQueryError::JobFailed { msg }) => {
+                Err(BigQueryError::TransactionError { statement, msg })
+            }
+            Err(err) => Err(err),
         }
     }
 }
@@ -88,6 +531,7 @@ pub struct Job {
     inner_client: Arc<InnerClient>,
     inner_job: structs::job::Job,
     project_id: String,
+    cancel_on_abort: bool,
 }
 
 impl fmt::Debug for Job {
@@ -95,49 +539,642 @@ impl fmt::Debug for Job {
         f.debug_struct("Job")
             .field("inner_job", &self.inner_job)
             .field("project_id", &self.project_id)
+            .field("cancel_on_abort", &self.cancel_on_abort)
             .finish()
     }
 }
 
-#[derive(Default)]
+// Deserializes the rows of a page of query results starting at `start_index` in the result set,
+// reading the values in place
+// The `location` query parameter of job requests, url-encoded by the request builder
+fn location_query(location: Option<&str>) -> Vec<(&'static str, &str)> {
+    location
+        .map(|location| ("location", location))
+        .into_iter()
+        .collect()
+}
+
+fn deserialize_rows<T: Deserialize>(
+    query_results: RawQueryResults<'_>,
+    start_index: usize,
+) -> Result<Vec<T>, BigQueryError> {
+    let schema = query_results
+        .schema
+        .ok_or(BigQueryError::MissingSchemaInQueryResponse)?;
+    let indices = T::create_deserialize_indices(&schema.fields)?;
+    query_results
+        .rows
+        .ok_or(BigQueryError::MissingRowsInQueryResponse)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            T::deserialize_raw(row, indices.root()).map_err(|err| err.at_row(start_index + i))
+        })
+        .collect()
+}
+
+/// Plan for reading the rows of a result, built once per schema by `create_deserialize_indices`.
+///
+/// Each struct reads a block of ops, one per field, followed by the blocks of its nested records
+/// and flattened structs. All blocks live in the same array, addressed by offsets relative to the
+/// block referring to them, so nesting doesn't allocate.
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Decoder {
-    pub indices: Vec<usize>,
-    pub recursive_indices: Vec<Box<Decoder>>,
+    pub ops: Vec<Op>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Index of the column read by the field, `usize::MAX` if it isn't read
+    Column(usize),
+    /// Column read by a field holding records or other column types, and the offset of their block
+    Nested { column: usize, offset: usize },
+    /// Offset of the block of a flattened struct
+    Flatten { offset: usize },
+    /// Value set by column types for themselves, like the INT64 mapping of enums
+    Tag(usize),
+}
+
+impl Op {
+    /// The column read by the op, `usize::MAX` if none.
+    pub fn column(self) -> usize {
+        match self {
+            Op::Column(column) | Op::Nested { column, .. } => column,
+            Op::Flatten { .. } | Op::Tag(_) => usize::MAX,
+        }
+    }
+}
+
+impl Decoder {
+    /// The block of the top-level struct.
+    pub fn root(&self) -> DecoderRef<'_> {
+        DecoderRef { ops: &self.ops }
+    }
+}
+
+/// A block of a [`Decoder`], passed to `deserialize`.
+#[derive(Debug, Clone, Copy)]
+pub struct DecoderRef<'a> {
+    ops: &'a [Op],
+}
+
+impl<'a> DecoderRef<'a> {
+    /// The column read by the field at `field_idx`, `usize::MAX` if none.
+    pub fn column(self, field_idx: usize) -> usize {
+        self.ops[field_idx].column()
+    }
+
+    /// The block of the records or flattened struct of the field at `field_idx`.
+    pub fn nested(self, field_idx: usize) -> DecoderRef<'a> {
+        match self.ops[field_idx] {
+            Op::Nested { offset, .. } | Op::Flatten { offset } => DecoderRef {
+                ops: &self.ops[offset..],
+            },
+            op => panic!(
+                "Expected a nested block for field {}, got {:?}",
+                field_idx, op
+            ),
+        }
+    }
+
+    /// The value a column type set for itself, 0 if none.
+    pub fn tag(self) -> usize {
+        match self.ops.first() {
+            Some(Op::Tag(tag)) => *tag,
+            _ => 0,
+        }
+    }
 }
 
 pub trait Deserialize
 where
     Self: Sized,
 {
+    /// Checks the schema and appends the block of the struct to `ops`.
+    fn create_decoder_ops(
+        schema_fields: &[TableFieldSchema],
+        ops: &mut Vec<Op>,
+    ) -> Result<(), BigQueryError>;
+    fn deserialize(row: TableRow, decoder: DecoderRef<'_>) -> Result<Self, BigQueryError>;
+    /// Checks the schema and builds the decoder passed to `deserialize`.
     fn create_deserialize_indices(
-        schema_fields: &Vec<TableFieldSchema>,
-    ) -> Result<Decoder, BigQueryError>;
-    fn deserialize(row: TableRow, decoder: &Decoder) -> Result<Self, BigQueryError>;
+        schema_fields: &[TableFieldSchema],
+    ) -> Result<Decoder, BigQueryError> {
+        let mut ops = Vec::new();
+        Self::create_decoder_ops(schema_fields, &mut ops)?;
+        Ok(Decoder { ops })
+    }
+    /// Appends the block of a struct reading some of the columns, used by `#[my_bq(flatten)]`.
+    /// Marks the columns it reads in `claimed` and adds the problems found to `mismatch` instead
+    /// of failing. The default implementation considers all columns read.
+    fn create_partial_decoder_ops(
+        schema_fields: &[TableFieldSchema],
+        claimed: &mut [bool],
+        mismatch: &mut SchemaMismatch,
+        ops: &mut Vec<Op>,
+    ) -> Result<(), BigQueryError> {
+        claimed.iter_mut().for_each(|claimed| *claimed = true);
+        match Self::create_decoder_ops(schema_fields, ops) {
+            Err(BigQueryError::RowSchemaMismatch(other)) => {
+                mismatch.fields.extend(other.fields);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+    /// Deserializes from a row shared with other structs, used by `#[my_bq(flatten)]`.
+    /// The derive takes the values it reads out of the row, the default implementation clones it.
+    fn deserialize_fields(
+        row: &mut TableRow,
+        decoder: DecoderRef<'_>,
+    ) -> Result<Self, BigQueryError> {
+        Self::deserialize(row.clone(), decoder)
+    }
+    /// Deserializes from a row borrowing the response bytes, used by `get_results`.
+    /// The derive reads the values in place, the default implementation converts the row into a
+    /// `TableRow` first.
+    fn deserialize_raw(row: RawRow<'_>, decoder: DecoderRef<'_>) -> Result<Self, BigQueryError> {
+        Self::deserialize(row.into_table_row(), decoder)
+    }
+    /// Columns read by the struct, used by [`Select`](crate::query::Select) to build the SELECT
+    /// list. The default implementation selects all columns.
+    fn select_columns() -> Vec<SelectColumn> {
+        Vec::new()
+    }
+    /// Same as `deserialize_fields`, for rows borrowing the response bytes.
+    fn deserialize_raw_fields(
+        row: &mut RawRow<'_>,
+        decoder: DecoderRef<'_>,
+    ) -> Result<Self, BigQueryError> {
+        Self::deserialize(row.clone().into_table_row(), decoder)
+    }
+}
+
+/// Reads the rows of any schema as they are, see [`Job::get_results_with_schema`].
+impl Deserialize for TableRow {
+    fn create_decoder_ops(
+        _schema_fields: &[TableFieldSchema],
+        _ops: &mut Vec<Op>,
+    ) -> Result<(), BigQueryError> {
+        Ok(())
+    }
+    fn deserialize(row: TableRow, _decoder: DecoderRef<'_>) -> Result<Self, BigQueryError> {
+        Ok(row)
+    }
+}
+
+/// Conversion of a struct into a row of a `tabledata.insertAll` request, see `#[derive(Serialize)]`.
+pub trait Serialize {
+    /// The `json` object of the row, mapping column names to values.
+    fn serialize(&self) -> serde_json::Map<String, serde_json::Value>;
+}
+
+/// The table schema a struct maps to, see `#[derive(Schema)]`.
+pub trait Schema {
+    fn schema_fields() -> Vec<TableFieldSchema>;
+    fn table_schema() -> TableSchema {
+        TableSchema {
+            fields: Self::schema_fields(),
+        }
+    }
+}
+
+/// Conversion of a single column value, implemented by records through [`Deserialize`] and by
+/// enums and newtype structs through `#[derive(Deserialize)]`.
+pub trait ColumnDeserialize
+where
+    Self: Sized,
+{
+    /// Checks the schema of the column and appends the block passed to `deserialize_column`.
+    fn create_column_decoder(
+        field: &TableFieldSchema,
+        ops: &mut Vec<Op>,
+    ) -> Result<(), BigQueryError>;
+    fn deserialize_column(value: Value, decoder: DecoderRef<'_>) -> Result<Self, BigQueryError>;
+    /// Same as `deserialize_column`, for values borrowing the response bytes.
+    fn deserialize_raw_column(
+        value: RawValue<'_>,
+        decoder: DecoderRef<'_>,
+    ) -> Result<Self, BigQueryError> {
+        Self::deserialize_column(value.into_value(), decoder)
+    }
+    /// Sub-columns read from a record column, empty to select the whole column.
+    fn select_fields() -> Vec<SelectColumn> {
+        Vec::new()
+    }
+    /// Whether a field of the type reads a whole REPEATED column, like a newtype around a `Vec`.
+    fn repeated() -> bool {
+        false
+    }
+}
+
+impl<T: Deserialize> ColumnDeserialize for T {
+    fn create_column_decoder(
+        field: &TableFieldSchema,
+        ops: &mut Vec<Op>,
+    ) -> Result<(), BigQueryError> {
+        if field.field_type != Type::Record {
+            return Err(BigQueryError::RowSchemaMismatch(
+                FieldMismatch::Mistyped {
+                    path: field.name.clone(),
+                    expected: "Record".to_string(),
+                    found: format!("{:?}", field.field_type),
+                }
+                .into(),
+            ));
+        }
+        match &field.fields {
+            Some(fields) => T::create_decoder_ops(fields, ops).map_err(|err| match err {
+                BigQueryError::RowSchemaMismatch(mut mismatch) => {
+                    if field.mode == Mode::Repeated {
+                        mismatch.prefix_paths(&format!("{}[].", field.name));
+                    } else {
+                        mismatch.prefix_paths(&format!("{}.", field.name));
+                    }
+                    BigQueryError::RowSchemaMismatch(mismatch)
+                }
+                err => err,
+            }),
+            None => Err(BigQueryError::RowSchemaMismatch(
+                FieldMismatch::Mistyped {
+                    path: field.name.clone(),
+                    expected: "Record with fields".to_string(),
+                    found: "Record without fields".to_string(),
+                }
+                .into(),
+            )),
+        }
+    }
+    fn deserialize_column(value: Value, decoder: DecoderRef<'_>) -> Result<Self, BigQueryError> {
+        match value {
+            Value::Record(row) => T::deserialize(row, decoder),
+            other_value => Err(BigQueryError::UnexpectedFieldType(format!(
+                "Expected Record value, found {:?}",
+                other_value
+            ))),
+        }
+    }
+    fn deserialize_raw_column(
+        value: RawValue<'_>,
+        decoder: DecoderRef<'_>,
+    ) -> Result<Self, BigQueryError> {
+        match value {
+            RawValue::Record(row) => T::deserialize_raw(row, decoder),
+            other_value => Err(BigQueryError::UnexpectedFieldType(format!(
+                "Expected Record value, found {:?}",
+                other_value
+            ))),
+        }
+    }
+    fn select_fields() -> Vec<SelectColumn> {
+        T::select_columns()
+    }
+}
+
+/// Conversion of a single column value, implemented by records through [`Serialize`] and by
+/// enums and newtype structs through `#[derive(Serialize)]`.
+pub trait ColumnSerialize {
+    fn serialize_column(&self) -> serde_json::Value;
+}
+
+impl<T: Serialize> ColumnSerialize for T {
+    fn serialize_column(&self) -> serde_json::Value {
+        serde_json::Value::Object(self.serialize())
+    }
+}
+
+/// Schema of a single column, implemented by records through [`Schema`] and by enums and
+/// newtype structs through `#[derive(Schema)]`.
+pub trait ColumnSchema {
+    /// `mode` is the mode implied by the field type wrapping this one, like NULLABLE for `Option<T>`.
+    fn column_schema(name: String, mode: Mode) -> TableFieldSchema;
+}
+
+impl<T: Schema> ColumnSchema for T {
+    fn column_schema(name: String, mode: Mode) -> TableFieldSchema {
+        TableFieldSchema {
+            name,
+            field_type: Type::Record,
+            mode,
+            fields: Some(T::schema_fields()),
+        }
+    }
 }
 
 impl Job {
-    async fn assert_job_completion(
+    pub fn job_id(&self) -> Option<&str> {
+        self.inner_job
+            .job_reference
+            .as_ref()
+            .and_then(|job| job.job_id.as_deref())
+    }
+    pub fn status(&self) -> Option<&JobStatus> {
+        self.inner_job.status.as_ref()
+    }
+    /// The SQL text of query jobs.
+    pub fn query(&self) -> Option<&str> {
+        self.inner_job
+            .configuration
+            .as_ref()
+            .and_then(|configuration| configuration.query.as_ref())
+            .and_then(|query| query.query.as_deref())
+    }
+    /// Statistics as returned by the API call that produced this job:
+    /// job insert for jobs from [`Client::post_query`], jobs.list for [`Job::child_jobs`].
+    pub fn statistics(&self) -> Option<&JobStatistics> {
+        self.inner_job.statistics.as_ref()
+    }
+    /// Waits for the job to finish, returning it with up to date status and statistics.
+    /// Fails with [`BigQueryError::JobFailed`] if the job finished with an error.
+    #[tracing::instrument(name = "bigquery.wait", skip(self), fields(job_id = field::Empty))]
+    pub async fn wait(&self) -> Result<Job, BigQueryError> {
+        let job_id = self
+            .job_id()
+            .ok_or(BigQueryError::MissingJobIdInGoogleApiResponse)?;
+        tracing::Span::current().record("job_id", job_id);
+        let api_url = self.job_url(job_id, "");
+        let tok = self.inner_client.token().await?;
+        let policy = again::RetryPolicy::exponential(Duration::from_millis(100))
+            .with_max_retries(100)
+            .with_max_delay(Duration::from_secs(10))
+            .with_jitter(true);
+        let job = policy
+            .retry_if(
+                || self.fetch_done_job(&api_url, &tok),
+                |err: &BigQueryError| matches!(err, BigQueryError::JobPending),
+            )
+            .await?;
+        if let Some(JobStatus {
+            error_result: Some(ErrorProto { message, .. }),
+            ..
+        }) = job.status
+        {
+            return Err(BigQueryError::JobFailed { msg: message });
+        }
+        Ok(Job {
+            inner_client: self.inner_client.clone(),
+            inner_job: job,
+            project_id: self.project_id.clone(),
+            cancel_on_abort: self.cancel_on_abort,
+        })
+    }
+    /// Requests the cancellation of the job. Cancellation is asynchronous: the returned job holds
+    /// the status at the time of the request, [`Job::wait`] returns once the job is done.
+    #[tracing::instrument(name = "bigquery.cancel", skip(self), fields(job_id = field::Empty))]
+    pub async fn cancel(&self) -> Result<Job, BigQueryError> {
+        let job_id = self
+            .job_id()
+            .ok_or(BigQueryError::MissingJobIdInGoogleApiResponse)?;
+        tracing::Span::current().record("job_id", job_id);
+        let api_url = self.job_url(job_id, "/cancel");
+        let tok = self.inner_client.token().await?;
+        let res = self
+            .inner_client
+            .send(
+                self.inner_client
+                    .reqwest_client
+                    .post(api_url)
+                    .query(&self.location_query())
+                    .bearer_auth(tok.as_str()),
+            )
+            .await?;
+        let response: JobCancelResponse = res.error_for_status()?.json().await?;
+        Ok(Job {
+            inner_client: self.inner_client.clone(),
+            inner_job: response.job.unwrap_or_else(|| self.inner_job.clone()),
+            project_id: self.project_id.clone(),
+            cancel_on_abort: self.cancel_on_abort,
+        })
+    }
+    // URL of the job, followed by `method`, to request with `location_query`
+    fn job_url(&self, job_id: &str, method: &str) -> String {
+        format!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/jobs/{job_id}{method}",
+            project_id = self.project_id,
+            job_id = job_id,
+            method = method,
+        )
+    }
+    // Query parameters selecting the location of the job
+    fn location_query(&self) -> Vec<(&'static str, &str)> {
+        location_query(
+            self.inner_job
+                .job_reference
+                .as_ref()
+                .and_then(|job| job.location.as_deref()),
+        )
+    }
+    async fn fetch_done_job(
         &self,
         api_url: &str,
         tok: &yup_oauth2::AccessToken,
-    ) -> Result<JobQueryResults, BigQueryError> {
+    ) -> Result<structs::job::Job, BigQueryError> {
+        self.inner_client
+            .increment_counter(metrics::POLL_ATTEMPTS, 1);
         let res = self
             .inner_client
-            .reqwest_client
-            .get(api_url.clone())
-            .bearer_auth(tok.as_str())
-            .send()
+            .get_with_retry_query(api_url, &self.location_query(), tok)
             .await?;
-        let query_results: JobQueryResults = res.json().await?;
-        if query_results.job_complete {
-            Ok(query_results)
+        let job: structs::job::Job = res.error_for_status()?.json().await?;
+        match job.status {
+            Some(JobStatus {
+                state: Some(State::Done),
+                ..
+            }) => Ok(job),
+            _ => Err(BigQueryError::JobPending),
+        }
+    }
+    /// Lists child jobs of a multi-statement script, one per executed statement, in execution order.
+    /// Each child can be queried for its own results with [`Job::get_results`].
+    /// Should be called after the script completes (i.e. after [`Job::get_results`] on the parent),
+    /// otherwise only the statements started so far are listed.
+    #[tracing::instrument(name = "bigquery.child_jobs", skip(self), fields(job_id = field::Empty))]
+    pub async fn child_jobs(&self) -> Result<Vec<Job>, BigQueryError> {
+        let job_id = self
+            .job_id()
+            .ok_or(BigQueryError::MissingJobIdInGoogleApiResponse)?;
+        tracing::Span::current().record("job_id", job_id);
+        let tok = self.inner_client.token().await?;
+        let mut children = Vec::new();
+        let mut page_token: Option<String> = None;
+        let api_url = format!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/jobs",
+            project_id = self.project_id,
+        );
+        loop {
+            let mut query = vec![("parentJobId", job_id), ("projection", "full")];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+            let res = self
+                .inner_client
+                .get_with_retry_query(&api_url, &query, &tok)
+                .await?;
+            let job_list: JobList = res.error_for_status()?.json().await?;
+            children.extend(job_list.jobs);
+            page_token = job_list.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        sort_by_creation_time(&mut children);
+        Ok(children
+            .into_iter()
+            .map(|inner_job| Job {
+                inner_client: self.inner_client.clone(),
+                inner_job,
+                project_id: self.project_id.clone(),
+                cancel_on_abort: false,
+            })
+            .collect())
+    }
+    #[tracing::instrument(
+        name = "bigquery.poll",
+        skip(self, api_url, tok),
+        fields(retries = field::Empty, latency_ms = field::Empty)
+    )]
+    async fn assert_job_completion(
+        &self,
+        api_url: &str,
+        tok: &yup_oauth2::AccessToken,
+        attempt: usize,
+    ) -> Result<impl AsRef<[u8]>, BigQueryError> {
+        let started = Instant::now();
+        self.inner_client
+            .increment_counter(metrics::POLL_ATTEMPTS, 1);
+        let res = self.inner_client.get_with_retry(api_url, tok).await?;
+        let bytes = res.bytes().await?;
+        tracing::Span::current().record(
+            "latency_ms",
+            self.inner_client
+                .record_latency(metrics::POLL_LATENCY, started),
+        );
+        if serde_json::from_slice::<JobCompletion>(&bytes)?.job_complete {
+            Ok(bytes)
         } else {
             Err(BigQueryError::JobPending)
         }
     }
-    pub async fn get_results<T: Deserialize>(&self) -> Result<Vec<T>, BigQueryError>
+    // Body of the first response of `api_url`, a `getQueryResults` request, for which the job is
+    // complete, polling while it runs
+    async fn complete_results(
+        &self,
+        api_url: &str,
+        tok: &yup_oauth2::AccessToken,
+    ) -> Result<Box<dyn AsRef<[u8]> + Send>, BigQueryError> {
+        let res = self.inner_client.get_with_retry(api_url, tok).await?;
+        let bytes = res.bytes().await?;
+        if serde_json::from_slice::<JobCompletion>(&bytes)?.job_complete {
+            return Ok(Box::new(bytes));
+        }
+        debug!(target: "bigquery_client", "waiting for job completion");
+        let policy = again::RetryPolicy::exponential(Duration::from_millis(100))
+            .with_max_retries(100)
+            .with_max_delay(Duration::from_secs(10))
+            .with_jitter(true);
+        let attempt = AtomicUsize::new(0);
+        let polled_bytes = policy
+            .retry_if(
+                || {
+                    let attempt = attempt.fetch_add(1, Ordering::Relaxed);
+                    self.assert_job_completion(api_url, tok, attempt)
+                },
+                |err: &BigQueryError| matches!(err, BigQueryError::JobPending),
+            )
+            .await?;
+        Ok(Box::new(polled_bytes))
+    }
+    /// When [`Job::get_results`] or [`Job::get_results_with_schema`] fails or is dropped before
+    /// finishing, also requests the cancellation of the job on the server, see [`Job::cancel`].
+    /// Their page fetches are aborted either way.
+    pub fn with_cancel_on_abort(mut self) -> Self {
+        self.cancel_on_abort = true;
+        self
+    }
+    /// Waits for the job and reads all its results, fetching up to 10 pages at once. Dropping
+    /// the returned future aborts the page fetches, the first failed one aborts the others.
+    pub async fn get_results<T>(&self) -> Result<Vec<T>, BigQueryError>
+    where
+        T: Deserialize + Send + 'static,
+    {
+        let (_, rows) = self.get_results_with_schema().await?;
+        Ok(rows)
+    }
+    /// Same as [`Job::get_results`], also returning the schema of the result, empty for
+    /// statements without results. Reading [`TableRow`]s gives the rows of any query as is.
+    #[tracing::instrument(
+        name = "bigquery.get_results",
+        skip(self),
+        fields(job_id = field::Empty, total_rows = field::Empty, retries = field::Empty)
+    )]
+    pub async fn get_results_with_schema<T>(&self) -> Result<(TableSchema, Vec<T>), BigQueryError>
+    where
+        T: Deserialize + Send + 'static,
+    {
+        let mut cancel_on_drop = CancelOnDrop(self.cancel_on_abort.then(|| self.clone()));
+        let res = self.fetch_results().await;
+        if let Some(job) = cancel_on_drop.0.take() {
+            if res.is_err() {
+                job.cancel_quietly().await;
+            }
+        }
+        res
+    }
+    /// Waits for the job and reads up to `max_results` rows of its results from `start_index`, to
+    /// read only the first rows or to process a large result one page at a time. BigQuery returns
+    /// fewer rows when they exceed about 10MB, the page is empty past the last row.
+    #[tracing::instrument(
+        name = "bigquery.get_results_page",
+        skip(self),
+        fields(job_id = field::Empty, total_rows = field::Empty, retries = field::Empty)
+    )]
+    pub async fn get_results_page<T: Deserialize>(
+        &self,
+        start_index: usize,
+        max_results: usize,
+    ) -> Result<ResultsPage<T>, BigQueryError> {
+        let job_id = self
+            .job_id()
+            .ok_or(BigQueryError::MissingJobIdInGoogleApiResponse)?;
+        tracing::Span::current().record("job_id", job_id);
+        let api_url = format!(
+            "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/queries/{job_id}?maxResults={max_results}&startIndex={start_index}",
+            project_id = self.project_id,
+            job_id = job_id,
+            max_results = max_results,
+            start_index = start_index,
+        );
+        let tok = self.inner_client.token().await?;
+        let bytes = self.complete_results(&api_url, &tok).await?;
+        let query_results: RawQueryResults = serde_json::from_slice((*bytes).as_ref())?;
+        let total_rows: usize = query_results
+            .total_rows
+            .as_ref()
+            .ok_or(BigQueryError::MissingTotalRowsInQueryResponse)?
+            .parse()?;
+        tracing::Span::current().record("total_rows", total_rows);
+        let schema = query_results.schema.clone().unwrap_or_default();
+        // Pages past the last row come without rows
+        let rows = if query_results.rows.is_none() && start_index >= total_rows {
+            Vec::new()
+        } else {
+            deserialize_rows(query_results, start_index)?
+        };
+        Ok(ResultsPage {
+            schema,
+            total_rows,
+            rows,
+        })
+    }
+    // Failures are only logged, to report the error that led to the cancellation instead
+    async fn cancel_quietly(&self) {
+        if let Err(err) = self.cancel().await {
+            tracing::warn!(target: "bigquery_client", "Failed to cancel job: {}", err);
+        }
+    }
+    async fn fetch_results<T>(&self) -> Result<(TableSchema, Vec<T>), BigQueryError>
     where
-        T: Send + 'static,
+        T: Deserialize + Send + 'static,
     {
         if let Some(job_id) = self
             .inner_job
@@ -145,149 +1182,702 @@ impl Job {
             .as_ref()
             .and_then(|job| job.job_id.as_ref())
         {
+            tracing::Span::current().record("job_id", job_id.as_str());
             let api_url = format!(
                 "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/queries/{job_id}",
                 project_id = self.project_id,
                 job_id = job_id,
             );
-            let tok = self.inner_client.authenticator.token(SCOPES).await?;
-            let res = again::retry_if(
-                || {
-                    self.inner_client
-                        .reqwest_client
-                        .get(api_url.clone())
-                        .bearer_auth(tok.as_str())
-                        .send()
-                },
-                |err: &reqwest::Error| {
-                    // we want to retry hyper::Error(IncompleteMessage), which seems to happen rarely during https requests
-                    // https://github.com/hyperium/hyper/issues/2136
-                    err.is_request() || err.is_body()
-                },
-            )
-            .await?;
-            let mut query_results: JobQueryResults = res.json().await?;
-            if !query_results.job_complete {
-                debug!(target: "bigquery_client", "waiting for job completion");
-                let policy = again::RetryPolicy::exponential(Duration::from_millis(100))
-                    .with_max_retries(100)
-                    .with_max_delay(Duration::from_secs(10))
-                    .with_jitter(true);
-                query_results = policy
-                    .retry_if(
-                        || self.assert_job_completion(&api_url, &tok),
-                        |err: &BigQueryError| matches!(err, BigQueryError::JobPending),
-                    )
-                    .await?;
-            }
+            let tok = self.inner_client.token().await?;
+            let bytes = self.complete_results(&api_url, &tok).await?;
+            let query_results: RawQueryResults = serde_json::from_slice((*bytes).as_ref())?;
             debug!(target: "bigquery_client", "job is done, fetching results");
-            let total_rows: usize = if let Some(total_rows) = query_results.total_rows {
+            let total_rows: usize = if let Some(total_rows) = &query_results.total_rows {
                 total_rows.parse()?
             } else {
                 return Err(BigQueryError::MissingTotalRowsInQueryResponse);
             };
+            tracing::Span::current().record("total_rows", total_rows);
+            let schema = query_results.schema.clone().unwrap_or_default();
             if total_rows == 0 {
-                return Ok(Vec::new());
+                return Ok((schema, Vec::new()));
             }
-            let schema = &query_results
-                .schema
-                .ok_or(BigQueryError::MissingSchemaInQueryResponse)?;
-            let indices = T::create_deserialize_indices(&schema.fields)?;
-            let mut result: Vec<T> = query_results
-                .rows
-                .ok_or(BigQueryError::MissingRowsInQueryResponse)?
-                .into_iter()
-                .map(|row| T::deserialize(row, &indices))
-                .collect::<Result<Vec<T>, BigQueryError>>()?;
-            if query_results.page_token.is_none() {
+            let last_page = query_results.page_token.is_none();
+            let mut result: Vec<T> = deserialize_rows(query_results, 0)?;
+            if last_page {
                 // got all results - return immediately!
-                return Ok(result);
+                return Ok((schema, result));
             } else {
                 let results_per_request = 1000;
                 let max_concurrency = 10;
                 let sem = Arc::new(Semaphore::new(max_concurrency));
                 let start_index = result.len();
-                let mut futures: Vec<tokio::task::JoinHandle<Result<Vec<_>, BigQueryError>>> =
-                    Vec::new();
-                for i in (start_index..total_rows).step_by(results_per_request) {
+                // All the pages are spawned at once, waiting for a permit, so that none is left
+                // to start once a page fails
+                let mut pages = PageTasks(Vec::new());
+                for (page_index, i) in (start_index..total_rows)
+                    .step_by(results_per_request)
+                    .enumerate()
+                {
+                    let max_results = min(total_rows - i, results_per_request);
                     debug!(target: "bigquery_client",
                         "Requesting from {}, size {}",
                         i,
-                        min(total_rows - i, results_per_request)
+                        max_results
                     );
                     let inner_client = self.inner_client.clone();
-                    let api_url = format!(
-                        "https://bigquery.googleapis.com/bigquery/v2/projects/{project_id}/queries/{job_id}?maxResults={max_results}&startIndex={start_index}",
-                        project_id = self.project_id,
-                        job_id = job_id,
-                        start_index=i,
-                        max_results = min(total_rows - i, results_per_request)
-                    );
+                    let api_url = api_url.clone();
                     let tok = tok.clone();
-                    let permit = Arc::clone(&sem).acquire_owned().await;
-                    let future = task::spawn(async move {
-                        let _permit = permit;
-                        let res = again::retry_if(
-                            || {
+                    let sem = Arc::clone(&sem);
+                    let span = tracing::debug_span!(
+                        "bigquery.page_fetch",
+                        job_id = job_id.as_str(),
+                        page_index,
+                        start_index = i,
+                        max_results,
+                        bytes = field::Empty,
+                        latency_ms = field::Empty,
+                        retries = field::Empty,
+                    );
+                    let future = task::spawn(
+                        async move {
+                            let _permit = sem.acquire_owned().await;
+                            let started = Instant::now();
+                            let mut result: Vec<T> = Vec::with_capacity(max_results);
+                            let mut total_bytes = 0;
+                            // Responses over 10MB come back with fewer rows than requested, the
+                            // rest of the page is requested again. A page without rows stops,
+                            // leaving the missing rows to the row count check
+                            while result.len() < max_results {
+                                let page_url = format!(
+                                    "{}?maxResults={}&startIndex={}",
+                                    api_url,
+                                    max_results - result.len(),
+                                    i + result.len()
+                                );
+                                let res = inner_client.get_with_retry(&page_url, &tok).await?;
+                                let bytes = res.bytes().await?;
+                                total_bytes += bytes.len();
                                 inner_client
-                                    .reqwest_client
-                                    .get(api_url.clone())
-                                    .bearer_auth(tok.as_str())
-                                    .send()
-                            },
-                            |err: &reqwest::Error| {
-                                // we want to retry hyper::Error(IncompleteMessage), which seems to happen rarely during https requests
-                                // https://github.com/hyperium/hyper/issues/2136
-                                err.is_request() || err.is_body()
-                            },
-                        )
-                        .await?;
-                        let bytes = res.bytes().await?;
-                        let query_results = task::spawn_blocking(move || {
-                            serde_json::from_slice::<JobQueryResults>(&bytes)
-                        })
-                        .await??;
-                        let schema = &query_results
-                            .schema
-                            .ok_or(BigQueryError::MissingSchemaInQueryResponse)?;
-                        let indices = T::create_deserialize_indices(&schema.fields)?;
-                        let result: Vec<T> = query_results
-                            .rows
-                            .ok_or(BigQueryError::MissingRowsInQueryResponse)?
-                            .into_iter()
-                            .map(|row| T::deserialize(row, &indices))
-                            .collect::<Result<Vec<T>, BigQueryError>>()?;
-                        debug!(
-                          target: "bigquery_client",
-                            "Finished requesting from {}, size {}",
-                            i,
-                            min(total_rows - i, results_per_request)
-                        );
-                        Ok(result)
-                    });
-                    futures.push(future);
+                                    .record_histogram(metrics::PAGE_BYTES, bytes.len() as f64);
+                                let start_index = i + result.len();
+                                let rows: Vec<T> = task::spawn_blocking(move || {
+                                    deserialize_rows(serde_json::from_slice(&bytes)?, start_index)
+                                })
+                                .await??;
+                                if rows.is_empty() {
+                                    break;
+                                }
+                                result.extend(rows);
+                            }
+                            let span = tracing::Span::current();
+                            span.record("bytes", total_bytes);
+                            span.record(
+                                "latency_ms",
+                                inner_client.record_latency(metrics::PAGE_LATENCY, started),
+                            );
+                            debug!(
+                              target: "bigquery_client",
+                                "Finished requesting from {}, size {}",
+                                i,
+                                max_results
+                            );
+                            Ok(result)
+                        }
+                        .instrument(span),
+                    );
+                    pages.0.push(future);
                 }
-                let results = futures::future::join_all(futures).await;
+                // Returns on the first error, `pages` aborting the other fetches when dropped
+                let results = futures::future::try_join_all(
+                    pages.0.iter_mut().map(|page| async move { page.await? }),
+                )
+                .await?;
                 for new_result in results {
-                    result.extend(new_result??);
+                    result.extend(new_result);
                 }
             }
             if result.len() != total_rows {
-                panic!("Expected result len {}, got {}", total_rows, result.len());
+                return Err(BigQueryError::NotEnoughRows {
+                    expected: total_rows,
+                    found: result.len(),
+                });
             }
-            Ok(result)
+            Ok((schema, result))
         } else {
             Err(BigQueryError::MissingJobIdInGoogleApiResponse)
         }
     }
 }
 
+/// Rows of a result read by [`Job::get_results_page`].
+#[derive(Debug, Clone)]
+pub struct ResultsPage<T> {
+    /// Schema of the result, empty for statements without results
+    pub schema: TableSchema,
+    /// Number of rows of the whole result
+    pub total_rows: usize,
+    pub rows: Vec<T>,
+}
+
+// Only the completion flag of a `getQueryResults` response, skipping the rows
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JobCompletion {
+    job_complete: bool,
+}
+
+// The page fetches of `get_results`, aborted when dropped so that none outlives the call. Pages
+// being deserialized are left to finish, blocking tasks can't be aborted.
+struct PageTasks<T>(Vec<task::JoinHandle<Result<Vec<T>, BigQueryError>>>);
+
+impl<T> Drop for PageTasks<T> {
+    fn drop(&mut self) {
+        for page in &self.0 {
+            page.abort();
+        }
+    }
+}
+
+// Requests the cancellation of the job when dropped before being disarmed by taking it out, see
+// `Job::with_cancel_on_abort`
+struct CancelOnDrop(Option<Job>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(job) = self.0.take() {
+            // Outside of a runtime, there's no way to send the request
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move { job.cancel_quietly().await });
+            }
+        }
+    }
+}
+
+// jobs.list returns the most recent jobs first, while statement order is the natural one for scripts.
+// Reversing first keeps the statements created in the same millisecond in order.
+fn sort_by_creation_time(jobs: &mut [structs::job::Job]) {
+    jobs.reverse();
+    jobs.sort_by_key(|job| {
+        job.statistics
+            .as_ref()
+            .and_then(|statistics| statistics.creation_time.as_ref())
+            .and_then(|creation_time| creation_time.parse::<i64>().ok())
+            .unwrap_or(i64::MAX)
+    });
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::structs::{table_row::TableRow, table_schema::TableSchema};
-
     use super::*;
-    use my_bq_proc::Deserialize;
+    use my_bq_proc::{Deserialize, Schema, Serialize};
+
+    #[tokio::test]
+    async fn test_missing_credentials_file() {
+        let res = Client::try_new("/nonexistent/authorized_user.json").await;
+        assert!(matches!(
+            res,
+            Err(BigQueryError::AuthenticatorSetupError(_))
+        ));
+        let res = Client::from_service_account_key_file("/nonexistent/service_account.json").await;
+        assert!(matches!(
+            res,
+            Err(BigQueryError::AuthenticatorSetupError(_))
+        ));
+    }
+
+    #[test]
+    fn test_credentials_env_var() {
+        assert!(matches!(
+            credentials_key_path(None),
+            Err(BigQueryError::MissingCredentialsEnvVar { var }) if var == CREDENTIALS_ENV_VAR
+        ));
+        assert_eq!(
+            credentials_key_path(Some("key.json".to_string())).unwrap(),
+            "key.json"
+        );
+        assert!(matches!(
+            check_credentials_env_var_unset(Some(OsStr::new("key.json"))),
+            Err(BigQueryError::UnexpectedCredentialsEnvVar { var }) if var == CREDENTIALS_ENV_VAR
+        ));
+        assert!(check_credentials_env_var_unset(None).is_ok());
+    }
+
+    #[derive(Default)]
+    struct RecordingMetrics {
+        counters: std::sync::Mutex<Vec<(&'static str, u64)>>,
+        histograms: std::sync::Mutex<Vec<(&'static str, f64)>>,
+    }
+
+    impl Metrics for RecordingMetrics {
+        fn increment_counter(&self, name: &'static str, value: u64) {
+            self.counters.lock().unwrap().push((name, value));
+        }
+        fn record_histogram(&self, name: &'static str, value: f64) {
+            self.histograms.lock().unwrap().push((name, value));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        use crate::fixtures::testing::*;
+        let pending = || {
+            exchange(
+                "GET",
+                "/queries/job_1",
+                None,
+                serde_json::json!({"jobComplete": false}),
+            )
+        };
+        let metrics = Arc::new(RecordingMetrics::default());
+        // Pending on the first request and the first poll
+        let client = replay_client(
+            "metrics",
+            &[
+                insert_job("job_1"),
+                pending(),
+                pending(),
+                results("job_1", None, 2, &["a"], false),
+                results("job_1", Some("maxResults=1&startIndex=1"), 2, &["b"], true),
+            ],
+        )
+        .with_metrics(metrics.clone());
+        let rows: Vec<TableRow> = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap()
+            .get_results()
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            *metrics.counters.lock().unwrap(),
+            [(metrics::POLL_ATTEMPTS, 1), (metrics::POLL_ATTEMPTS, 1)]
+        );
+        let histograms = metrics.histograms.lock().unwrap();
+        let names: Vec<_> = histograms.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                metrics::JOB_INSERT_LATENCY,
+                metrics::POLL_LATENCY,
+                metrics::POLL_LATENCY,
+                metrics::PAGE_BYTES,
+                metrics::PAGE_LATENCY,
+            ]
+        );
+        let page_bytes = histograms[3].1;
+        assert!(page_bytes > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_page_tasks_abort_on_drop() {
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        let pages = PageTasks::<TableRow>(vec![task::spawn(async move {
+            let _sender = sender;
+            futures::future::pending::<()>().await;
+            Ok(Vec::new())
+        })]);
+        drop(pages);
+        // The sender is dropped with the aborted task
+        assert!(receiver.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_results_abort_on_drop() {
+        use crate::fixtures::testing::*;
+        let client = replay_client(
+            "get_results_abort_on_drop",
+            &[
+                insert_job("job_1"),
+                results("job_1", None, 2, &["a"], false),
+                results("job_1", Some("maxResults=1&startIndex=1"), 2, &["b"], true),
+                cancel_job("job_1"),
+            ],
+        );
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap()
+            .with_cancel_on_abort();
+        let mut rows = Box::pin(job.get_results::<TableRow>());
+        // Replayed responses are ready at once: the first poll reads the first page and spawns
+        // the fetch of the second one, which only starts once the test yields
+        assert!(futures::poll!(rows.as_mut()).is_pending());
+        drop(rows);
+        let cancel = "POST /jobs/job_1/cancel".to_string();
+        for _ in 0..100 {
+            if requests(&client).contains(&cancel) {
+                break;
+            }
+            task::yield_now().await;
+        }
+        assert_eq!(
+            requests(&client),
+            ["POST /jobs", "GET /queries/job_1", cancel.as_str()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_job_location_encoding() {
+        use crate::fixtures::testing::*;
+        let job = serde_json::json!({
+            "jobReference": {"projectId": "project", "jobId": "job_1", "location": "EU&x"},
+            "status": {"state": "DONE"},
+        });
+        let client = replay_client(
+            "job_location_encoding",
+            &[
+                exchange("GET", "/jobs/job_1", Some("location=EU%26x"), job.clone()),
+                exchange(
+                    "POST",
+                    "/jobs/job_1/cancel",
+                    Some("location=EU%26x"),
+                    serde_json::json!({ "job": job }),
+                ),
+            ],
+        );
+        let job = client
+            .get_job("project", "job_1", Some("EU&x"))
+            .await
+            .unwrap();
+        job.wait().await.unwrap();
+        job.cancel().await.unwrap();
+        assert_eq!(
+            requests(&client),
+            [
+                "GET /jobs/job_1?location=EU%26x",
+                "GET /jobs/job_1?location=EU%26x",
+                "POST /jobs/job_1/cancel?location=EU%26x",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_results_short_pages() {
+        use crate::fixtures::testing::*;
+        let client = replay_client(
+            "get_results_short_pages",
+            &[
+                insert_job("job_1"),
+                results("job_1", None, 4, &["a"], false),
+                // Cut short like responses over 10MB
+                results("job_1", Some("maxResults=3&startIndex=1"), 4, &["b"], true),
+                results(
+                    "job_1",
+                    Some("maxResults=2&startIndex=2"),
+                    4,
+                    &["c", "d"],
+                    true,
+                ),
+            ],
+        );
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap();
+        let rows = job.get_results::<TableRow>().await.unwrap();
+        let names: Vec<_> = rows.iter().map(|row| row.fields[0].value.clone()).collect();
+        assert_eq!(
+            names,
+            ["a", "b", "c", "d"].map(|name| Some(Value::String(name.to_string())))
+        );
+
+        // A page without rows leaves the result short
+        let client = replay_client(
+            "get_results_missing_rows",
+            &[
+                insert_job("job_1"),
+                results("job_1", None, 3, &["a"], false),
+                results("job_1", Some("maxResults=2&startIndex=1"), 3, &["b"], true),
+                results("job_1", Some("maxResults=1&startIndex=2"), 3, &[], true),
+            ],
+        );
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap();
+        assert!(matches!(
+            job.get_results::<TableRow>().await,
+            Err(BigQueryError::NotEnoughRows {
+                expected: 3,
+                found: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_child_jobs_order() {
+        let job_list = r#"{
+            "kind": "bigquery#jobList",
+            "jobs": [
+              {
+                "id": "project:US.script_job_abc_1",
+                "jobReference": {"projectId": "project", "jobId": "script_job_abc_1", "location": "US"},
+                "state": "DONE",
+                "statistics": {
+                  "creationTime": "1660000001000",
+                  "parentJobId": "job_parent",
+                  "query": {"statementType": "SELECT", "totalBytesProcessed": "10"},
+                  "scriptStatistics": {
+                    "evaluationKind": "STATEMENT",
+                    "stackFrames": [{"startLine": 2, "startColumn": 1, "endLine": 2, "endColumn": 10, "text": "SELECT 1"}]
+                  }
+                },
+                "status": {"state": "DONE"}
+              },
+              {
+                "id": "project:US.script_job_abc_0",
+                "jobReference": {"projectId": "project", "jobId": "script_job_abc_0", "location": "US"},
+                "state": "DONE",
+                "statistics": {
+                  "creationTime": "1660000000000",
+                  "parentJobId": "job_parent",
+                  "query": {"statementType": "CREATE_TABLE"}
+                },
+                "status": {"state": "DONE"}
+              }
+            ]
+          }"#;
+        let job_list: JobList = serde_json::from_str(job_list).unwrap();
+        assert!(job_list.next_page_token.is_none());
+        let mut jobs = job_list.jobs;
+        sort_by_creation_time(&mut jobs);
+        let job_ids: Vec<_> = jobs
+            .iter()
+            .map(|job| {
+                job.job_reference
+                    .as_ref()
+                    .unwrap()
+                    .job_id
+                    .as_deref()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(job_ids, vec!["script_job_abc_0", "script_job_abc_1"]);
+        let statistics = jobs[1].statistics.as_ref().unwrap();
+        assert_eq!(statistics.parent_job_id.as_deref(), Some("job_parent"));
+        let script_statistics = statistics.script_statistics.as_ref().unwrap();
+        assert_eq!(
+            script_statistics.stack_frames[0].text.as_deref(),
+            Some("SELECT 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_child_jobs() {
+        use crate::fixtures::testing::*;
+        let child = |job_id: &str, creation_time: &str| {
+            serde_json::json!({
+                "jobReference": {"projectId": "project", "jobId": job_id},
+                "statistics": {"creationTime": creation_time, "parentJobId": "job_parent"},
+                "status": {"state": "DONE"}
+            })
+        };
+        // Most recent first, the last two statements started in the same millisecond. The page
+        // token needs url-encoding.
+        let client = replay_client(
+            "child_jobs",
+            &[
+                insert_job("job_parent"),
+                exchange(
+                    "GET",
+                    "/jobs",
+                    Some("parentJobId=job_parent&projection=full"),
+                    serde_json::json!({
+                        "jobs": [child("child_2", "1660000001000")],
+                        "nextPageToken": "a+b/c="
+                    }),
+                ),
+                exchange(
+                    "GET",
+                    "/jobs",
+                    Some("parentJobId=job_parent&projection=full&pageToken=a%2Bb%2Fc%3D"),
+                    serde_json::json!({
+                        "jobs": [child("child_1", "1660000000000"), child("child_0", "1660000000000")]
+                    }),
+                ),
+                results("child_0", None, 1, &["a"], true),
+            ],
+        );
+        let children = client
+            .post_query(
+                "project",
+                "SELECT 1; SELECT 2; SELECT 'a' AS name".to_string(),
+            )
+            .await
+            .unwrap()
+            .child_jobs()
+            .await
+            .unwrap();
+        let job_ids: Vec<_> = children.iter().map(|job| job.job_id().unwrap()).collect();
+        assert_eq!(job_ids, ["child_0", "child_1", "child_2"]);
+        let rows: Vec<TableRow> = children[0].get_results().await.unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_jobs() {
+        use crate::fixtures::testing::*;
+        let job = |job_id: &str| serde_json::json!({"jobReference": {"jobId": job_id}});
+        let client = replay_client(
+            "list_jobs",
+            &[
+                exchange(
+                    "GET",
+                    "/jobs",
+                    Some("projection=full&maxResults=3"),
+                    serde_json::json!({"jobs": [job("job_3")], "nextPageToken": "a+b/c="}),
+                ),
+                exchange(
+                    "GET",
+                    "/jobs",
+                    Some("projection=full&maxResults=2&pageToken=a%2Bb%2Fc%3D"),
+                    serde_json::json!({"jobs": [job("job_2"), job("job_1")], "nextPageToken": "d"}),
+                ),
+            ],
+        );
+        let jobs = client.list_jobs("project", 3).await.unwrap();
+        let job_ids: Vec<_> = jobs.iter().map(|job| job.job_id().unwrap()).collect();
+        assert_eq!(job_ids, ["job_3", "job_2", "job_1"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_results_page() {
+        use crate::fixtures::testing::*;
+        let query = "maxResults=2&startIndex=0";
+        let client = replay_client(
+            "get_results_page",
+            &[
+                insert_job("job_1"),
+                exchange(
+                    "GET",
+                    "/queries/job_1",
+                    Some(query),
+                    serde_json::json!({"jobComplete": false}),
+                ),
+                results("job_1", Some(query), 3, &["a", "b"], false),
+                results("job_1", Some("maxResults=2&startIndex=2"), 3, &["c"], true),
+                exchange(
+                    "GET",
+                    "/queries/job_1",
+                    Some("maxResults=2&startIndex=3"),
+                    serde_json::json!({
+                        "jobComplete": true,
+                        "totalRows": "3",
+                        "schema": {"fields": [{"name": "name", "type": "STRING", "mode": "NULLABLE"}]}
+                    }),
+                ),
+            ],
+        );
+        let job = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap();
+        // The first page waits for the job
+        let page = job.get_results_page::<TableRow>(0, 2).await.unwrap();
+        assert_eq!(page.total_rows, 3);
+        assert_eq!(page.schema.fields[0].name, "name");
+        assert_eq!(page.rows.len(), 2);
+        let page = job.get_results_page::<TableRow>(2, 2).await.unwrap();
+        assert_eq!(page.rows.len(), 1);
+        let page = job.get_results_page::<TableRow>(3, 2).await.unwrap();
+        assert!(page.rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transaction_errors() {
+        use crate::fixtures::testing::*;
+        let done = |job_id: &str, body: serde_json::Value| {
+            exchange("GET", &format!("/jobs/{}", job_id), None, body)
+        };
+        let mut rejected = insert_job("");
+        rejected.status = 400;
+        rejected.response_body = serde_json::json!({
+            "error": {"code": 400, "message": "Syntax error", "status": "INVALID_ARGUMENT"}
+        });
+        let client = replay_client(
+            "transaction_errors",
+            &[
+                insert_job("session_job"),
+                done(
+                    "session_job",
+                    serde_json::json!({
+                        "status": {"state": "DONE"},
+                        "statistics": {"sessionInfo": {"sessionId": "session_1"}}
+                    }),
+                ),
+                insert_job("commit_job"),
+                done(
+                    "commit_job",
+                    serde_json::json!({"status": {"state": "DONE", "errorResult": {
+                        "reason": "invalidQuery", "location": "", "message": "Transaction was aborted"
+                    }}}),
+                ),
+                rejected,
+            ],
+        );
+        let session = client.create_session("project").await.unwrap();
+        assert_eq!(session.session_id(), "session_1");
+        match session.commit().await {
+            Err(BigQueryError::TransactionError { statement, msg }) => {
+                assert_eq!(statement, "COMMIT TRANSACTION");
+                assert_eq!(msg, "Transaction was aborted");
+            }
+            res => panic!("Expected a transaction error, got {:?}", res),
+        }
+        match session.rollback().await {
+            Err(BigQueryError::TransactionError { statement, msg }) => {
+                assert_eq!(statement, "ROLLBACK TRANSACTION");
+                assert_eq!(msg, "Syntax error");
+            }
+            res => panic!("Expected a transaction error, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_error_status() {
+        use crate::fixtures::testing::*;
+        let mut not_found = exchange(
+            "GET",
+            "/jobs/job_1",
+            None,
+            serde_json::json!({"error": {"code": 404, "message": "Not found: Job project:job_1"}}),
+        );
+        not_found.status = 404;
+        let client = replay_client("wait_error_status", &[insert_job("job_1"), not_found]);
+        let job = client
+            .post_query("project", "SELECT 1".to_string())
+            .await
+            .unwrap();
+        match job.wait().await {
+            Err(BigQueryError::ApiRequestError(err)) => {
+                assert_eq!(err.status(), Some(reqwest::StatusCode::NOT_FOUND))
+            }
+            res => panic!("Expected a request error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_session_job_serialization() {
+        let mut job = structs::job::Job::new("SELECT 1".into());
+        let query = job.configuration.as_mut().unwrap().query.as_mut().unwrap();
+        query.create_session = Some(true);
+        query.connection_properties = Some(vec![ConnectionProperty {
+            key: "session_id".into(),
+            value: "CgwKCmZha2Vfc2Vzc2lvbhAB".into(),
+        }]);
+        let json = serde_json::to_value(&job).unwrap();
+        assert_eq!(
+            json["configuration"]["query"],
+            serde_json::json!({
+                "query": "SELECT 1",
+                "useLegacySql": false,
+                "createSession": true,
+                "connectionProperties": [{"key": "session_id", "value": "CgwKCmZha2Vfc2Vzc2lvbhAB"}]
+            })
+        );
+    }
 
     #[derive(Deserialize)]
     struct MyStruct2 {
@@ -348,8 +1938,8 @@ mod tests {
             let row: TableRow = serde_json::from_str(row).unwrap();
             assert_eq!(row.fields.len(), 4);
             let decoder = MyStruct2::create_deserialize_indices(&schema.fields).unwrap();
-            assert_eq!(decoder.indices.len(), 4);
-            let rec = MyStruct2::deserialize(row, &decoder).unwrap();
+            assert_eq!(decoder.ops.len(), 4);
+            let rec = MyStruct2::deserialize(row, decoder.root()).unwrap();
             assert_eq!(rec.analytics, "Yes");
             assert_eq!(rec.ads, "Yes2");
             assert_eq!(rec.int_val, 13337);
@@ -374,8 +1964,8 @@ mod tests {
             let row: TableRow = serde_json::from_str(row).unwrap();
             assert_eq!(row.fields.len(), 4);
             let decoder = MyStruct2::create_deserialize_indices(&schema.fields).unwrap();
-            assert_eq!(decoder.indices.len(), 4);
-            let rec = MyStruct2::deserialize(row, &decoder).unwrap();
+            assert_eq!(decoder.ops.len(), 4);
+            let rec = MyStruct2::deserialize(row, decoder.root()).unwrap();
             assert_eq!(rec.analytics, "Yes");
             assert_eq!(rec.ads, "Yes2");
             assert_eq!(rec.int_val, 13337);
@@ -383,6 +1973,271 @@ mod tests {
         }
     }
 
+    #[derive(Deserialize)]
+    struct Primitives {
+        flag: bool,
+        optional_flag: Option<bool>,
+        small_int: i32,
+        unsigned_int: u32,
+        big_unsigned_int: u64,
+        wide_int: i128,
+        wide_unsigned_int: u128,
+        pointer_sized_int: isize,
+        float_value: f32,
+        initial: char,
+    }
+
+    #[test]
+    fn test_primitive_types() {
+        let schema = r#"{
+            "fields": [
+                {"name": "flag", "type": "BOOLEAN", "mode": "NULLABLE"},
+                {"name": "optional_flag", "type": "BOOL", "mode": "NULLABLE"},
+                {"name": "small_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "unsigned_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "big_unsigned_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "wide_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "wide_unsigned_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "pointer_sized_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "float_value", "type": "FLOAT64", "mode": "NULLABLE"},
+                {"name": "initial", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = Primitives::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+                {"v": "true"},
+                {"v": null},
+                {"v": "-42"},
+                {"v": "4000000000"},
+                {"v": "9223372036854775807"},
+                {"v": "-9223372036854775808"},
+                {"v": "9223372036854775807"},
+                {"v": "-7"},
+                {"v": "0.5"},
+                {"v": "x"}
+            ]
+          }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = Primitives::deserialize(row, decoder.root()).unwrap();
+        assert!(rec.flag);
+        assert_eq!(rec.optional_flag, None);
+        assert_eq!(rec.small_int, -42);
+        assert_eq!(rec.unsigned_int, 4000000000);
+        assert_eq!(rec.big_unsigned_int, 9223372036854775807);
+        assert_eq!(rec.wide_int, -9223372036854775808);
+        assert_eq!(rec.wide_unsigned_int, 9223372036854775807);
+        assert_eq!(rec.pointer_sized_int, -7);
+        assert_eq!(rec.float_value, 0.5);
+        assert_eq!(rec.initial, 'x');
+
+        // -1 does not fit into u32
+        let row = r#"{"f": [
+                {"v": "false"},
+                {"v": "true"},
+                {"v": "0"},
+                {"v": "-1"},
+                {"v": "0"},
+                {"v": "0"},
+                {"v": "0"},
+                {"v": "0"},
+                {"v": "0.5"},
+                {"v": "x"}
+            ]
+          }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        match Primitives::deserialize(row, decoder.root()) {
+            Err(BigQueryError::InvalidValue(invalid)) => {
+                assert_eq!(invalid.row, None);
+                assert_eq!(invalid.path, "unsigned_int");
+                assert_eq!(invalid.value, "-1");
+                assert!(matches!(
+                    invalid.source,
+                    BigQueryError::IntOutOfRangeError(_)
+                ));
+            }
+            _ => panic!("expected an invalid value"),
+        }
+
+        let schema = r#"{
+            "fields": [
+                {"name": "flag", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "optional_flag", "type": "BOOL", "mode": "NULLABLE"},
+                {"name": "small_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "unsigned_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "big_unsigned_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "wide_int", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "wide_unsigned_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "pointer_sized_int", "type": "INT64", "mode": "NULLABLE"},
+                {"name": "float_value", "type": "FLOAT64", "mode": "NULLABLE"},
+                {"name": "initial", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        assert!(matches!(
+            Primitives::create_deserialize_indices(&schema.fields),
+            Err(BigQueryError::RowSchemaMismatch(_))
+        ));
+    }
+
+    #[derive(Deserialize)]
+    struct RepeatedScalars {
+        tags: Vec<String>,
+        scores: Vec<i64>,
+        weights: Option<Vec<f64>>,
+        flags: Vec<Option<bool>>,
+    }
+
+    #[test]
+    fn test_repeated_scalars() {
+        let schema = r#"{
+            "fields": [
+                {"name": "tags", "type": "STRING", "mode": "REPEATED"},
+                {"name": "scores", "type": "INTEGER", "mode": "REPEATED"},
+                {"name": "weights", "type": "FLOAT", "mode": "REPEATED"},
+                {"name": "flags", "type": "BOOLEAN", "mode": "REPEATED"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = RepeatedScalars::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+                {"v": [{"v": "a"}, {"v": "b"}]},
+                {"v": [{"v": "1"}, {"v": "-2"}, {"v": "3"}]},
+                {"v": []},
+                {"v": [{"v": "true"}, {"v": null}]}
+            ]
+          }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = RepeatedScalars::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.tags, vec!["a", "b"]);
+        assert_eq!(rec.scores, vec![1, -2, 3]);
+        assert_eq!(rec.weights, Some(vec![]));
+        assert_eq!(rec.flags, vec![Some(true), None]);
+
+        let row = r#"{"f": [
+                {"v": []},
+                {"v": []},
+                {"v": null},
+                {"v": []}
+            ]
+          }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = RepeatedScalars::deserialize(row, decoder.root()).unwrap();
+        assert!(rec.tags.is_empty());
+        assert_eq!(rec.weights, None);
+
+        // repeated fields require REPEATED mode
+        let schema = r#"{
+            "fields": [
+                {"name": "tags", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "scores", "type": "INTEGER", "mode": "REPEATED"},
+                {"name": "weights", "type": "FLOAT", "mode": "REPEATED"},
+                {"name": "flags", "type": "BOOLEAN", "mode": "REPEATED"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        assert!(matches!(
+            RepeatedScalars::create_deserialize_indices(&schema.fields),
+            Err(BigQueryError::RowSchemaMismatch(_))
+        ));
+    }
+
+    #[derive(Deserialize)]
+    struct JsonColumn {
+        payload: serde_json::Value,
+        optional_payload: Option<serde_json::Value>,
+    }
+
+    #[test]
+    fn test_json_column() {
+        let schema = r#"{
+            "fields": [
+                {"name": "payload", "type": "JSON", "mode": "NULLABLE"},
+                {"name": "optional_payload", "type": "JSON", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = JsonColumn::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": "{\"a\":[1,2]}"}, {"v": null}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = JsonColumn::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.payload["a"][0], 1);
+        assert!(rec.optional_payload.is_none());
+    }
+
+    #[cfg(all(feature = "chrono", feature = "decimal", feature = "bytes"))]
+    #[test]
+    fn test_temporal_and_decimal_columns() {
+        use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+        use rust_decimal::Decimal;
+
+        #[derive(Deserialize)]
+        struct Row {
+            #[my_bq(chrono)]
+            event_timestamp: DateTime<Utc>,
+            #[my_bq(chrono)]
+            event_date: NaiveDate,
+            #[my_bq(chrono)]
+            local_datetime: Option<NaiveDateTime>,
+            local_time: chrono::NaiveTime,
+            #[my_bq(decimal)]
+            price: Decimal,
+            payload: Vec<u8>,
+        }
+
+        let schema = r#"{
+            "fields": [
+                {"name": "event_timestamp", "type": "TIMESTAMP", "mode": "NULLABLE"},
+                {"name": "event_date", "type": "DATE", "mode": "NULLABLE"},
+                {"name": "local_datetime", "type": "DATETIME", "mode": "NULLABLE"},
+                {"name": "local_time", "type": "TIME", "mode": "NULLABLE"},
+                {"name": "price", "type": "NUMERIC", "mode": "NULLABLE"},
+                {"name": "payload", "type": "BYTES", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = Row::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+                {"v": "1.648823841187011E9"},
+                {"v": "2022-04-01"},
+                {"v": "2022-04-01T14:37:21.187011"},
+                {"v": "14:37:21"},
+                {"v": "19.99"},
+                {"v": "aGVsbG8="}
+            ]
+          }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = Row::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.event_timestamp.timestamp_micros(), 1648823841187011);
+        assert_eq!(rec.event_date, NaiveDate::from_ymd_opt(2022, 4, 1).unwrap());
+        assert_eq!(rec.local_datetime.unwrap().date(), rec.event_date);
+        assert_eq!(rec.local_time, NaiveTime::from_hms_opt(14, 37, 21).unwrap());
+        assert_eq!(rec.price.to_string(), "19.99");
+        assert_eq!(rec.payload, b"hello");
+    }
+
+    #[test]
+    fn test_bare_special_type_names() {
+        // Without #[my_bq(decimal)], a `Decimal` is whatever is imported under that name
+        mod money {
+            #[derive(Debug, PartialEq, my_bq_proc::Deserialize)]
+            pub struct Decimal(pub String);
+        }
+        use money::Decimal;
+
+        #[derive(Deserialize)]
+        struct Row {
+            price: Decimal,
+        }
+
+        let schema = r#"{"fields": [{"name": "price", "type": "STRING", "mode": "NULLABLE"}]}"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = Row::create_deserialize_indices(&schema.fields).unwrap();
+        let row: TableRow = serde_json::from_str(r#"{"f": [{"v": "19.99 EUR"}]}"#).unwrap();
+        let rec = Row::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.price, Decimal("19.99 EUR".into()));
+    }
+
     #[derive(Deserialize)]
     struct PrivacyInfo {
         analytics_storage: String,
@@ -427,14 +2282,16 @@ mod tests {
         let row: TableRow = serde_json::from_str(row).unwrap();
         assert_eq!(row.fields.len(), 3);
         let decoder = PrivacyInfo::create_deserialize_indices(&schema.fields).unwrap();
-        assert_eq!(decoder.indices.len(), 3);
-        let rec = PrivacyInfo::deserialize(row, &decoder).unwrap();
+        assert_eq!(decoder.ops.len(), 3);
+        let rec = PrivacyInfo::deserialize(row, decoder.root()).unwrap();
         assert_eq!(rec.analytics_storage, "Yes");
         assert_eq!(rec.ads_storage, "Yes");
         assert_eq!(rec.uses_transient_token, "No");
     }
 
+    // user_properties values also have a set_timestamp_micros column
     #[derive(Deserialize)]
+    #[my_bq(ignore_extra_columns)]
     struct JsonValue {
         string_value: Option<String>,
         int_value: Option<i64>,
@@ -488,8 +2345,8 @@ mod tests {
         let row: TableRow = serde_json::from_str(row).unwrap();
         assert_eq!(row.fields.len(), 4);
         let decoder = JsonValue::create_deserialize_indices(&schema.fields).unwrap();
-        assert_eq!(decoder.indices.len(), 4);
-        let rec = JsonValue::deserialize(row, &decoder).unwrap();
+        assert_eq!(decoder.ops.len(), 4);
+        let rec = JsonValue::deserialize(row, decoder.root()).unwrap();
         assert!(rec.string_value.is_none());
         assert!(rec.int_value.is_none());
         assert!(rec.float_value.is_none());
@@ -569,8 +2426,22 @@ mod tests {
         let row: TableRow = serde_json::from_str(row).unwrap();
         assert_eq!(row.fields.len(), 2);
         let decoder = EventParam::create_deserialize_indices(&schema.fields).unwrap();
-        assert_eq!(decoder.indices.len(), 2);
-        let rec = EventParam::deserialize(row, &decoder).unwrap();
+        // The block of the value record follows the fields of EventParam
+        assert_eq!(
+            decoder.ops,
+            vec![
+                Op::Column(0),
+                Op::Nested {
+                    column: 1,
+                    offset: 2
+                },
+                Op::Column(0),
+                Op::Column(1),
+                Op::Column(2),
+                Op::Column(3),
+            ]
+        );
+        let rec = EventParam::deserialize(row, decoder.root()).unwrap();
         assert_eq!(rec.key, "appIsInBackground");
         assert_eq!(rec.value.string_value, None);
         assert_eq!(rec.value.int_value, Some(0));
@@ -578,6 +2449,708 @@ mod tests {
         assert_eq!(rec.value.double_value, None);
     }
 
+    #[derive(Deserialize)]
+    struct OptionalRecords {
+        privacy_info: Option<PrivacyInfo>,
+        event_params: Option<Vec<EventParam>>,
+    }
+
+    #[test]
+    fn test_optional_records() {
+        let schema = r#"{
+            "fields": [
+              {
+                "name": "privacy_info",
+                "type": "RECORD",
+                "mode": "NULLABLE",
+                "fields": [
+                  {"name": "analytics_storage", "type": "STRING", "mode": "NULLABLE"},
+                  {"name": "ads_storage", "type": "STRING", "mode": "NULLABLE"},
+                  {"name": "uses_transient_token", "type": "STRING", "mode": "NULLABLE"}
+                ]
+              },
+              {
+                "name": "event_params",
+                "type": "RECORD",
+                "mode": "REPEATED",
+                "fields": [
+                  {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+                  {
+                    "name": "value",
+                    "type": "RECORD",
+                    "mode": "NULLABLE",
+                    "fields": [
+                      {"name": "string_value", "type": "STRING", "mode": "NULLABLE"},
+                      {"name": "int_value", "type": "INTEGER", "mode": "NULLABLE"},
+                      {"name": "float_value", "type": "FLOAT", "mode": "NULLABLE"},
+                      {"name": "double_value", "type": "FLOAT", "mode": "NULLABLE"}
+                    ]
+                  }
+                ]
+              }
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = OptionalRecords::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": null}, {"v": null}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = OptionalRecords::deserialize(row, decoder.root()).unwrap();
+        assert!(rec.privacy_info.is_none());
+        assert!(rec.event_params.is_none());
+
+        let row = r#"{"f": [
+            {"v": {"f": [{"v": "Yes"}, {"v": "No"}, {"v": "No"}]}},
+            {"v": [
+              {"v": {"f": [{"v": "engaged"}, {"v": {"f": [{"v": null}, {"v": "1"}, {"v": null}, {"v": null}]}}]}}
+            ]}
+          ]
+        }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = OptionalRecords::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.privacy_info.unwrap().analytics_storage, "Yes");
+        let event_params = rec.event_params.unwrap();
+        assert_eq!(event_params.len(), 1);
+        assert_eq!(event_params[0].key, "engaged");
+        assert_eq!(event_params[0].value.int_value, Some(1));
+    }
+
+    #[test]
+    fn test_invalid_value_context() {
+        let results = r#"{
+            "totalRows": "2",
+            "jobComplete": true,
+            "schema": {"fields": [
+              {"name": "privacy_info", "type": "RECORD", "mode": "NULLABLE", "fields": [
+                {"name": "analytics_storage", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "ads_storage", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "uses_transient_token", "type": "STRING", "mode": "NULLABLE"}
+              ]},
+              {"name": "event_params", "type": "RECORD", "mode": "REPEATED", "fields": [
+                {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "value", "type": "RECORD", "mode": "NULLABLE", "fields": [
+                  {"name": "string_value", "type": "STRING", "mode": "NULLABLE"},
+                  {"name": "int_value", "type": "INTEGER", "mode": "NULLABLE"},
+                  {"name": "float_value", "type": "FLOAT", "mode": "NULLABLE"},
+                  {"name": "double_value", "type": "FLOAT", "mode": "NULLABLE"}
+                ]}
+              ]}
+            ]},
+            "rows": [
+              {"f": [{"v": null}, {"v": []}]},
+              {"f": [{"v": null}, {"v": [
+                {"v": {"f": [{"v": "engaged"}, {"v": {"f": [{"v": null}, {"v": "1O"}, {"v": null}, {"v": null}]}}]}}
+              ]}]}
+            ]
+          }"#;
+        let results: RawQueryResults = serde_json::from_str(results).unwrap();
+        match deserialize_rows::<OptionalRecords>(results, 1000) {
+            Err(BigQueryError::InvalidValue(invalid)) => {
+                assert_eq!(invalid.row, Some(1001));
+                assert_eq!(invalid.path, "event_params[].value.int_value");
+                assert_eq!(invalid.value, "1O");
+                assert_eq!(invalid.expected, "Integer");
+                assert!(matches!(
+                    invalid.source,
+                    BigQueryError::IntConversionError(_)
+                ));
+                assert!(invalid.to_string().starts_with(
+                    "Failed to convert '1O' of column 'event_params[].value.int_value' in row 1001 \
+                     to Integer"
+                ));
+            }
+            _ => panic!("expected an invalid value"),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[my_bq(schema = "tests/schemas/events.json", ignore_extra_columns)]
+    struct CheckedEvent {
+        event_name: String,
+        event_params: Vec<EventParam>,
+        #[my_bq(alias = "user_pseudo_id")]
+        user_id: String,
+        #[my_bq(default)]
+        country: Option<String>,
+    }
+
+    #[test]
+    fn test_schema_file() {
+        // the derive checked the same file at compile time
+        let schema = include_str!("../tests/schemas/events.json");
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = CheckedEvent::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+            {"v": "20221018"}, {"v": "1666051200000000"}, {"v": "page_view"},
+            {"v": [{"v": {"f": [{"v": "engaged"}, {"v": {"f": [{"v": null}, {"v": "1"}, {"v": null}, {"v": null}]}}]}}]},
+            {"v": "u1"}
+          ]
+        }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = CheckedEvent::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.event_name, "page_view");
+        assert_eq!(rec.event_params[0].key, "engaged");
+        assert_eq!(rec.event_params[0].value.int_value, Some(1));
+        assert_eq!(rec.user_id, "u1");
+        assert_eq!(rec.country, None);
+    }
+
+    mod upper_case {
+        use crate::error::BigQueryError;
+        use crate::structs::row_field::Value;
+
+        pub fn deserialize(value: Option<Value>) -> Result<String, BigQueryError> {
+            match value {
+                Some(Value::String(val)) => Ok(val.to_uppercase()),
+                _ => Ok(String::new()),
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    #[my_bq(rename_all = "camelCase")]
+    struct Device {
+        device_category: String,
+        #[my_bq(rename = "os")]
+        operating_system: String,
+    }
+
+    #[derive(Deserialize)]
+    #[my_bq(rename_all = "SCREAMING_SNAKE_CASE")]
+    struct FieldAttributes {
+        user_id: String,
+        #[my_bq(default)]
+        missing_count: i64,
+        #[my_bq(default)]
+        present_count: i64,
+        #[my_bq(skip)]
+        skipped: Vec<String>,
+        #[my_bq(rename = "country", with = "upper_case")]
+        country: String,
+        #[my_bq(flatten)]
+        device: Device,
+    }
+
+    #[test]
+    fn test_field_attributes() {
+        let schema = r#"{
+            "fields": [
+              {"name": "deviceCategory", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "USER_ID", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "PRESENT_COUNT", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "country", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "os", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = FieldAttributes::create_deserialize_indices(&schema.fields).unwrap();
+        let row =
+            r#"{"f": [{"v": "mobile"}, {"v": "u1"}, {"v": "3"}, {"v": "fr"}, {"v": "Android"}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = FieldAttributes::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.user_id, "u1");
+        assert_eq!(rec.missing_count, 0);
+        assert_eq!(rec.present_count, 3);
+        assert!(rec.skipped.is_empty());
+        assert_eq!(rec.country, "FR");
+        assert_eq!(rec.device.device_category, "mobile");
+        assert_eq!(rec.device.operating_system, "Android");
+
+        // columns of flattened structs are required like the others
+        let res = FieldAttributes::create_deserialize_indices(&schema.fields[1..]);
+        assert!(matches!(res, Err(BigQueryError::RowSchemaMismatch(_))));
+    }
+
+    #[derive(Deserialize)]
+    #[my_bq(rename_all = "camelCase", case_insensitive)]
+    struct VersionedSchema {
+        user_id: String,
+        #[my_bq(alias = "ts", alias = "event_time")]
+        timestamp: i64,
+    }
+
+    #[test]
+    fn test_aliases_and_case_insensitive() {
+        let schema = r#"{
+            "fields": [
+              {"name": "event_time", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "UserId", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = VersionedSchema::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": "42"}, {"v": "u1"}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = VersionedSchema::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.user_id, "u1");
+        assert_eq!(rec.timestamp, 42);
+
+        let schema = r#"{
+            "fields": [
+              {"name": "userId", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "TS", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "timestamp", "type": "INTEGER", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let res = VersionedSchema::create_deserialize_indices(&schema.fields);
+        assert!(matches!(
+            res,
+            Err(BigQueryError::RowSchemaMismatch(mismatch))
+                if mismatch.fields == [FieldMismatch::Ambiguous {
+                    path: "timestamp".into(),
+                    columns: vec!["TS".into(), "timestamp".into()],
+                }]
+        ));
+    }
+
+    // Only the schemas of the Strict* structs are checked
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct StrictValue {
+        string_value: Option<String>,
+        int_value: Option<i64>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct StrictParam {
+        key: String,
+        value: StrictValue,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct StrictEvent {
+        event_name: String,
+        event_params: Vec<StrictParam>,
+        user_id: String,
+    }
+
+    #[derive(Deserialize)]
+    #[my_bq(ignore_extra_columns)]
+    struct LenientEvent {
+        event_name: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Keys(Vec<String>);
+
+    #[derive(Deserialize)]
+    struct KeyedEvent {
+        keys: Keys,
+    }
+
+    #[test]
+    fn test_schema_mismatch_report() {
+        let schema = r#"{
+            "fields": [
+              {"name": "event_date", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "event_name", "type": "INTEGER", "mode": "NULLABLE"},
+              {
+                "name": "event_params",
+                "type": "RECORD",
+                "mode": "REPEATED",
+                "fields": [
+                  {"name": "key", "type": "STRING", "mode": "REPEATED"},
+                  {
+                    "name": "value",
+                    "type": "RECORD",
+                    "mode": "NULLABLE",
+                    "fields": [
+                      {"name": "string_value", "type": "STRING", "mode": "NULLABLE"},
+                      {"name": "int_value", "type": "STRING", "mode": "NULLABLE"},
+                      {"name": "float_value", "type": "FLOAT", "mode": "NULLABLE"}
+                    ]
+                  }
+                ]
+              }
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let mismatch = match StrictEvent::create_deserialize_indices(&schema.fields) {
+            Err(BigQueryError::RowSchemaMismatch(mismatch)) => mismatch,
+            _ => panic!("expected a schema mismatch"),
+        };
+        assert_eq!(
+            mismatch.fields,
+            [
+                FieldMismatch::Mistyped {
+                    path: "event_name".into(),
+                    expected: "String".into(),
+                    found: "Integer".into(),
+                },
+                FieldMismatch::Mistyped {
+                    path: "event_params[].key".into(),
+                    expected: "Nullable or Required".into(),
+                    found: "Repeated".into(),
+                },
+                FieldMismatch::Mistyped {
+                    path: "event_params[].value.int_value".into(),
+                    expected: "Integer".into(),
+                    found: "String".into(),
+                },
+                FieldMismatch::Extra {
+                    path: "event_params[].value.float_value".into(),
+                },
+                FieldMismatch::Missing {
+                    path: "user_id".into(),
+                },
+                FieldMismatch::Extra {
+                    path: "event_date".into(),
+                },
+            ]
+        );
+        assert!(mismatch
+            .to_string()
+            .starts_with("Expected String for field 'event_name', got Integer; "));
+
+        let schema = r#"{
+            "fields": [
+              {"name": "event_date", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "event_name", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = LenientEvent::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": "20221018"}, {"v": "page_view"}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = LenientEvent::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.event_name, "page_view");
+
+        // Newtypes around a `Vec` take the REPEATED column
+        let schema = r#"{
+            "fields": [{"name": "keys", "type": "STRING", "mode": "REPEATED"}]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = KeyedEvent::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": [{"v": "a"}, {"v": "b"}]}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = KeyedEvent::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.keys, Keys(vec!["a".to_string(), "b".to_string()]));
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize, Schema)]
+    #[my_bq(rename_all = "snake_case")]
+    enum Storage {
+        Granted = 1,
+        #[my_bq(rename = "no")]
+        Denied,
+        NotSet = 10,
+    }
+
+    // Can't derive Serialize, which would write "unknown" for the values it caught
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[my_bq(rename_all = "snake_case")]
+    enum LenientStorage {
+        Granted,
+        #[my_bq(other)]
+        Unknown,
+    }
+
+    #[derive(Deserialize)]
+    struct ConsentHistory {
+        history: Vec<LenientStorage>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize, Schema)]
+    struct UserId(String);
+
+    #[derive(Deserialize, Serialize, Schema)]
+    struct Consent {
+        user_id: UserId,
+        analytics_storage: Storage,
+        ads_storage: Option<Storage>,
+        history: Vec<Storage>,
+    }
+
+    #[test]
+    fn test_enums_and_newtypes() {
+        let schema = r#"{
+            "fields": [
+              {"name": "user_id", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "analytics_storage", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "ads_storage", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "history", "type": "STRING", "mode": "REPEATED"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = Consent::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+            {"v": "u1"}, {"v": "granted"}, {"v": "2"},
+            {"v": [{"v": "no"}, {"v": "not_set"}]}
+          ]
+        }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = Consent::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.user_id, UserId("u1".into()));
+        assert_eq!(rec.analytics_storage, Storage::Granted);
+        assert_eq!(rec.ads_storage, Some(Storage::Denied));
+        assert_eq!(rec.history, vec![Storage::Denied, Storage::NotSet]);
+
+        assert_eq!(
+            serde_json::Value::Object(rec.serialize()),
+            serde_json::json!({
+                "user_id": "u1",
+                "analytics_storage": "granted",
+                "ads_storage": "no",
+                "history": ["no", "not_set"]
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Consent::schema_fields()).unwrap(),
+            serde_json::json!([
+                {"name": "user_id", "type": "STRING", "mode": "REQUIRED"},
+                {"name": "analytics_storage", "type": "STRING", "mode": "REQUIRED"},
+                {"name": "ads_storage", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "history", "type": "STRING", "mode": "REPEATED"}
+            ])
+        );
+
+        let mut schema = schema;
+        schema.fields[0].field_type = Type::Integer;
+        let res = Consent::create_deserialize_indices(&schema.fields);
+        assert!(matches!(
+            res,
+            Err(BigQueryError::RowSchemaMismatch(mismatch))
+                if mismatch.to_string() == "Expected String for field 'user_id', got Integer"
+        ));
+
+        let schema = r#"{
+            "fields": [{"name": "history", "type": "STRING", "mode": "REPEATED"}]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = ConsentHistory::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": [{"v": "granted"}, {"v": "revoked"}]}]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = ConsentHistory::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(
+            rec.history,
+            vec![LenientStorage::Granted, LenientStorage::Unknown]
+        );
+    }
+
+    #[test]
+    fn test_raw_rows() {
+        let row = r#"{"f": [
+            {"v": "plain"}, {"v": "esc\"aped"}, {"v": null},
+            {"v": [{"v": "1"}, {"v": null}]},
+            {"v": {"f": [{"v": "nested"}]}}
+          ]
+        }"#;
+        let raw: RawRow = serde_json::from_str(row).unwrap();
+        assert!(matches!(
+            &raw.fields[0].value,
+            Some(RawValue::String(std::borrow::Cow::Borrowed("plain")))
+        ));
+        let table_row: TableRow = serde_json::from_str(row).unwrap();
+        assert_eq!(raw.into_table_row(), table_row);
+
+        // derived types read raw rows like owned ones
+        let schema = r#"{
+            "fields": [
+              {"name": "deviceCategory", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "USER_ID", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "PRESENT_COUNT", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "country", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "os", "type": "STRING", "mode": "NULLABLE"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = FieldAttributes::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": "mobile"}, {"v": "u\u0031"}, {"v": "3"}, {"v": "fr"}, {"v": "Android"}]}"#;
+        let row: RawRow = serde_json::from_str(row).unwrap();
+        let rec = FieldAttributes::deserialize_raw(row, decoder.root()).unwrap();
+        assert_eq!(rec.user_id, "u1");
+        assert_eq!(rec.present_count, 3);
+        assert_eq!(rec.country, "FR");
+        assert_eq!(rec.device.device_category, "mobile");
+        assert_eq!(rec.device.operating_system, "Android");
+
+        let schema = r#"{
+            "fields": [
+              {"name": "user_id", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "analytics_storage", "type": "STRING", "mode": "NULLABLE"},
+              {"name": "ads_storage", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "history", "type": "STRING", "mode": "REPEATED"}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        let decoder = Consent::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [{"v": "u1"}, {"v": "granted"}, {"v": null}, {"v": [{"v": "no"}]}]}"#;
+        let row: RawRow = serde_json::from_str(row).unwrap();
+        let rec = Consent::deserialize_raw(row, decoder.root()).unwrap();
+        assert_eq!(rec.user_id, UserId("u1".into()));
+        assert_eq!(rec.analytics_storage, Storage::Granted);
+        assert_eq!(rec.ads_storage, None);
+        assert_eq!(rec.history, vec![Storage::Denied]);
+
+        let row = r#"{"f": [{"v": "u1"}, {"v": "granted"}, {"v": "2"}, {"v": "no"}]}"#;
+        let row: RawRow = serde_json::from_str(row).unwrap();
+        let res = Consent::deserialize_raw(row, decoder.root());
+        assert!(matches!(res, Err(BigQueryError::UnexpectedFieldType(_))));
+    }
+
+    mod records {
+        use my_bq_proc::{Deserialize, Schema, Serialize};
+
+        #[derive(Deserialize, Serialize, Schema)]
+        pub struct Param {
+            pub key: std::string::String,
+            pub value: core::option::Option<i64>,
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    struct Page<'a, T> {
+        items: Vec<T>,
+        first: Option<T>,
+        total: std::primitive::u32,
+        #[my_bq(flatten)]
+        meta: Meta<T>,
+        #[my_bq(skip)]
+        marker: std::marker::PhantomData<&'a ()>,
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    struct Meta<T> {
+        last: T,
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    struct Wrapper<T>(Vec<T>);
+
+    #[test]
+    fn test_generics_and_paths() {
+        let schema = r#"{
+            "fields": [
+              {"name": "items", "type": "RECORD", "mode": "REPEATED", "fields": [
+                {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "value", "type": "INTEGER", "mode": "NULLABLE"}
+              ]},
+              {"name": "first", "type": "RECORD", "mode": "NULLABLE", "fields": [
+                {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "value", "type": "INTEGER", "mode": "NULLABLE"}
+              ]},
+              {"name": "total", "type": "INTEGER", "mode": "NULLABLE"},
+              {"name": "last", "type": "RECORD", "mode": "NULLABLE", "fields": [
+                {"name": "key", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "value", "type": "INTEGER", "mode": "NULLABLE"}
+              ]}
+            ]
+          }"#;
+        let schema: TableSchema = serde_json::from_str(schema).unwrap();
+        type ParamPage = Page<'static, records::Param>;
+        let decoder = ParamPage::create_deserialize_indices(&schema.fields).unwrap();
+        let row = r#"{"f": [
+            {"v": [{"v": {"f": [{"v": "a"}, {"v": "1"}]}}]},
+            {"v": null},
+            {"v": "1"},
+            {"v": {"f": [{"v": "b"}, {"v": null}]}}
+          ]
+        }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = ParamPage::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.items[0].key, "a");
+        assert_eq!(rec.items[0].value, Some(1));
+        assert!(rec.first.is_none());
+        assert_eq!(rec.total, 1);
+        assert_eq!(rec.meta.last.key, "b");
+        let _ = rec.marker;
+
+        type WrappedPage = Page<'static, Wrapper<records::Param>>;
+        let fields = WrappedPage::schema_fields();
+        assert_eq!(fields[0].mode, Mode::Repeated);
+        assert_eq!(fields[0].fields.as_ref().unwrap()[0].name, "key");
+        assert_eq!(fields[1].mode, Mode::Repeated);
+        assert_eq!(fields[3].name, "last");
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    struct Item {
+        name: String,
+        quantity: Option<u32>,
+    }
+
+    #[derive(Deserialize, Serialize, Schema)]
+    #[my_bq(rename_all = "camelCase")]
+    struct Order {
+        order_id: i64,
+        total: f64,
+        paid: bool,
+        notes: Option<String>,
+        tags: Vec<String>,
+        items: Vec<Item>,
+        #[my_bq(skip)]
+        cached: Option<String>,
+        #[my_bq(flatten)]
+        device: Device,
+    }
+
+    #[test]
+    fn test_serialize_and_schema() {
+        let order = Order {
+            order_id: 9007199254740993,
+            total: 12.5,
+            paid: true,
+            notes: None,
+            tags: vec!["gift".into()],
+            items: vec![Item {
+                name: "pen".into(),
+                quantity: Some(2),
+            }],
+            cached: Some("ignored".into()),
+            device: Device {
+                device_category: "mobile".into(),
+                operating_system: "iOS".into(),
+            },
+        };
+        let row = serde_json::Value::Object(order.serialize());
+        assert_eq!(
+            row,
+            serde_json::json!({
+                "orderId": "9007199254740993",
+                "total": 12.5,
+                "paid": true,
+                "notes": null,
+                "tags": ["gift"],
+                "items": [{"name": "pen", "quantity": "2"}],
+                "deviceCategory": "mobile",
+                "os": "iOS"
+            })
+        );
+
+        let schema = serde_json::to_value(Order::table_schema()).unwrap();
+        assert_eq!(
+            schema,
+            serde_json::json!({"fields": [
+                {"name": "orderId", "type": "INTEGER", "mode": "REQUIRED"},
+                {"name": "total", "type": "FLOAT", "mode": "REQUIRED"},
+                {"name": "paid", "type": "BOOLEAN", "mode": "REQUIRED"},
+                {"name": "notes", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "tags", "type": "STRING", "mode": "REPEATED"},
+                {"name": "items", "type": "RECORD", "mode": "REPEATED", "fields": [
+                    {"name": "name", "type": "STRING", "mode": "REQUIRED"},
+                    {"name": "quantity", "type": "INTEGER", "mode": "NULLABLE"}
+                ]},
+                {"name": "deviceCategory", "type": "STRING", "mode": "REQUIRED"},
+                {"name": "os", "type": "STRING", "mode": "REQUIRED"}
+            ]})
+        );
+
+        // the derived schema is accepted by the derived deserializer
+        let decoder = Order::create_deserialize_indices(&Order::schema_fields()).unwrap();
+        let row = r#"{"f": [
+            {"v": "1"}, {"v": "2.5"}, {"v": "false"}, {"v": null}, {"v": []}, {"v": []},
+            {"v": "tablet"}, {"v": "Android"}
+          ]
+        }"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let rec = Order::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(rec.order_id, 1);
+        assert!(rec.cached.is_none());
+        assert_eq!(rec.device.operating_system, "Android");
+    }
+
     #[derive(Deserialize)]
     struct Struct3 {
         user_id: String,
@@ -811,8 +3384,9 @@ mod tests {
         let row: TableRow = serde_json::from_str(row).unwrap();
         assert_eq!(row.fields.len(), 6);
         let decoder = Struct3::create_deserialize_indices(&schema.fields).unwrap();
-        assert_eq!(decoder.indices.len(), 6);
-        let rec = Struct3::deserialize(row, &decoder).unwrap();
+        // 6 fields, 3 for privacy_info and 2 + 4 for each list of params
+        assert_eq!(decoder.ops.len(), 21);
+        let rec = Struct3::deserialize(row, decoder.root()).unwrap();
         assert_eq!(rec.user_id, "user1");
         assert_eq!(rec.event_timestamp, 1648823841187011);
         assert!(rec.user_id_nullable.is_none());
@@ -1,4 +1,7 @@
-use std::num::{ParseFloatError, ParseIntError};
+use std::char::ParseCharError;
+use std::fmt;
+use std::num::{ParseFloatError, ParseIntError, TryFromIntError};
+use std::str::ParseBoolError;
 
 use thiserror::Error;
 
@@ -6,10 +9,31 @@ use thiserror::Error;
 pub enum BigQueryError {
     #[error("Authentication error (error: {0})")]
     YupAuthError(#[from] yup_oauth2::Error),
+    #[error("Failed to set up authenticator (error: {0})")]
+    AuthenticatorSetupError(std::io::Error),
+    #[error("Environment variable {var} is not set")]
+    MissingCredentialsEnvVar { var: &'static str },
+    #[error("Environment variable {var} is set, refusing to use metadata server credentials")]
+    UnexpectedCredentialsEnvVar { var: &'static str },
+    #[error("Failed to start tokio runtime (error: {0})")]
+    RuntimeCreationError(std::io::Error),
     #[error("Serde json (error: {0})")]
     JsonDeserializationError(#[from] serde_json::Error),
     #[error("Int conversion error (error: {0})")]
     IntConversionError(#[from] ParseIntError),
+    #[error("Int conversion error: INT64 value out of range for the target type (error: {0})")]
+    IntOutOfRangeError(#[from] TryFromIntError),
+    #[error("Bool conversion error (error: {0})")]
+    BoolConversionError(#[from] ParseBoolError),
+    #[error("Char conversion error (error: {0})")]
+    CharConversionError(#[from] ParseCharError),
+    #[error("Failed to convert value '{value}' to {expected}")]
+    ValueConversionError {
+        value: String,
+        expected: &'static str,
+    },
+    #[error("{0}")]
+    InvalidValue(Box<InvalidValue>),
     #[error("Tokio join error (error: {0})")]
     TokioJoinError(#[from] tokio::task::JoinError),
     #[error("Float conversion error (error: {0})")]
@@ -26,14 +50,223 @@ pub enum BigQueryError {
     MissingTotalRowsInQueryResponse,
     #[error("Malformed google api response: expected fields len {expected}, found {found}")]
     NotEnoughFields { expected: usize, found: usize },
+    #[error("Malformed google api response: expected {expected} rows, found {found}")]
+    NotEnoughRows { expected: usize, found: usize },
     #[error("Malformed google api response: {0}")]
     UnexpectedFieldType(String),
     #[error("Struct deserialization error due to schema mismatch: {0}")]
-    RowSchemaMismatch(String),
+    RowSchemaMismatch(SchemaMismatch),
     #[error(" while running BigQuery job: {msg}")]
     JobInsertError { msg: String },
     #[error("Job is not complete yet")]
     JobPending,
+    #[error("BigQuery job failed: {msg}")]
+    JobFailed { msg: String },
+    #[error("Malformed google api response: missing session_id")]
+    MissingSessionIdInGoogleApiResponse,
+    #[error("Transaction error in '{statement}': {msg}")]
+    TransactionError {
+        statement: &'static str,
+        msg: String,
+    },
+    #[error("Fixture error in {path}: {msg}")]
+    FixtureError { path: String, msg: String },
+    #[error("No recorded response for {method} {url}")]
+    MissingFixture { method: String, url: String },
+}
+
+/// Every difference found between the schema of a result and the struct it's read into.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SchemaMismatch {
+    pub fields: Vec<FieldMismatch>,
+}
+
+/// A schema difference. `path` leads to the column through the records holding it,
+/// like `event_params[].value.int_value`, `[]` marking repeated records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldMismatch {
+    /// No column matches the field
+    Missing { path: String },
+    /// No field matches the column
+    Extra { path: String },
+    /// The column has the wrong type or mode
+    Mistyped {
+        path: String,
+        expected: String,
+        found: String,
+    },
+    /// Several columns match the field
+    Ambiguous { path: String, columns: Vec<String> },
+}
+
+impl SchemaMismatch {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Records that the field at `path`, matched by the column `first`, also matches `column`.
+    pub fn add_ambiguous(&mut self, path: &str, first: &str, column: &str) {
+        for field in &mut self.fields {
+            if let FieldMismatch::Ambiguous {
+                path: ambiguous_path,
+                columns,
+            } = field
+            {
+                if ambiguous_path == path {
+                    columns.push(column.to_string());
+                    return;
+                }
+            }
+        }
+        self.fields.push(FieldMismatch::Ambiguous {
+            path: path.to_string(),
+            columns: vec![first.to_string(), column.to_string()],
+        });
+    }
+
+    /// Prepends `prefix` to the paths, used for the fields of nested records.
+    pub fn prefix_paths(&mut self, prefix: &str) {
+        for field in &mut self.fields {
+            let path = match field {
+                FieldMismatch::Missing { path }
+                | FieldMismatch::Extra { path }
+                | FieldMismatch::Mistyped { path, .. }
+                | FieldMismatch::Ambiguous { path, .. } => path,
+            };
+            path.insert_str(0, prefix);
+        }
+    }
+}
+
+impl From<FieldMismatch> for SchemaMismatch {
+    fn from(field: FieldMismatch) -> Self {
+        SchemaMismatch {
+            fields: vec![field],
+        }
+    }
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, field) in self.fields.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", field)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FieldMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldMismatch::Missing { path } => {
+                write!(f, "Failed to find field '{}' in schema", path)
+            }
+            FieldMismatch::Extra { path } => write!(f, "Column '{}' isn't read by any field", path),
+            FieldMismatch::Mistyped {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} for field '{}', got {}",
+                expected, path, found
+            ),
+            FieldMismatch::Ambiguous { path, columns } => write!(
+                f,
+                "Field '{}' matches several columns: {}",
+                path,
+                columns.join(", ")
+            ),
+        }
+    }
+}
+
+/// A value of a row that failed to convert into its field.
+#[derive(Debug)]
+pub struct InvalidValue {
+    /// Index of the row within the result set, unknown when deserializing a row by hand
+    pub row: Option<usize>,
+    /// Path to the column through the records holding it, like `event_params[].value.int_value`
+    pub path: String,
+    /// The raw string value
+    pub value: String,
+    /// The type the value was converted to
+    pub expected: String,
+    pub source: BigQueryError,
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to convert '{}' of column '{}'",
+            self.value, self.path
+        )?;
+        if let Some(row) = self.row {
+            write!(f, " in row {}", row)?;
+        }
+        write!(f, " to {} (error: {})", self.expected, self.source)
+    }
+}
+
+impl std::error::Error for InvalidValue {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl BigQueryError {
+    /// Wraps the error of converting the raw `value` of a column, used by the derive.
+    pub fn invalid_value(
+        path: &str,
+        value: &str,
+        expected: &str,
+        source: impl Into<BigQueryError>,
+    ) -> Self {
+        BigQueryError::InvalidValue(Box::new(InvalidValue {
+            row: None,
+            path: path.to_string(),
+            value: value.to_string(),
+            expected: expected.to_string(),
+            source: source.into(),
+        }))
+    }
+
+    /// Adds the column `name` holding the invalid value to its path, from the innermost record
+    /// outwards. Value conversion errors get wrapped.
+    pub fn in_column(self, name: &str) -> Self {
+        match self {
+            BigQueryError::InvalidValue(mut invalid) => {
+                if !invalid.path.is_empty() && !invalid.path.starts_with('[') {
+                    invalid.path.insert(0, '.');
+                }
+                invalid.path.insert_str(0, name);
+                BigQueryError::InvalidValue(invalid)
+            }
+            BigQueryError::ValueConversionError { value, expected } => {
+                let source = BigQueryError::ValueConversionError {
+                    value: value.clone(),
+                    expected,
+                };
+                BigQueryError::invalid_value(name, &value, expected, source)
+            }
+            err => err,
+        }
+    }
+
+    /// Sets the index of the row holding the invalid value.
+    pub fn at_row(self, row: usize) -> Self {
+        match self {
+            BigQueryError::InvalidValue(mut invalid) => {
+                invalid.row = Some(row);
+                BigQueryError::InvalidValue(invalid)
+            }
+            err => err,
+        }
+    }
 }
 
 //unsafe impl Send for BigQueryError {}
@@ -0,0 +1,469 @@
+//! Record/replay of API calls, for regression tests on real-world result shapes.
+//!
+//! In record mode ([`Client::record_to`]) every request sent by the client, with the response it
+//! got, is saved as a numbered JSON file in a fixture directory. In replay mode
+//! ([`Client::replay_from`]) the client answers requests from those files without network
+//! access or credentials, matching them by method, path and query string. Requests with the same
+//! key, like the polls of a job, get the recorded responses in order, the last one being served
+//! again once they run out.
+//!
+//! The access token is sent in a header and isn't saved, fixtures hold the queries and their
+//! results though.
+//!
+//! [`Client::record_to`]: crate::client::Client::record_to
+//! [`Client::replay_from`]: crate::client::Client::replay_from
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::BigQueryError;
+
+/// A request and the response it got, stored in one fixture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    pub path: String,
+    /// Query string of the URL, without the leading `?`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<serde_json::Value>,
+    pub status: u16,
+    /// The response body, as a JSON string when it isn't valid JSON
+    pub response_body: serde_json::Value,
+}
+
+type Key = (String, String, Option<String>);
+
+impl Exchange {
+    fn key(&self) -> Key {
+        (self.method.clone(), self.path.clone(), self.query.clone())
+    }
+    fn response(&self) -> Result<reqwest::Response, BigQueryError> {
+        let body = match &self.response_body {
+            serde_json::Value::String(text) => text.clone().into_bytes(),
+            body => serde_json::to_vec(body)?,
+        };
+        let response = http::Response::builder()
+            .status(self.status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .map_err(|err| BigQueryError::FixtureError {
+                path: format!("{} {}", self.method, self.path),
+                msg: err.to_string(),
+            })?;
+        Ok(response.into())
+    }
+}
+
+fn request_key(request: &reqwest::Request) -> Key {
+    (
+        request.method().to_string(),
+        request.url().path().to_string(),
+        request.url().query().map(str::to_string),
+    )
+}
+
+fn fixture_error(path: &Path, err: impl ToString) -> BigQueryError {
+    BigQueryError::FixtureError {
+        path: path.display().to_string(),
+        msg: err.to_string(),
+    }
+}
+
+// Name of the fixture file of the `index`-th exchange
+fn fixture_name(index: usize) -> String {
+    format!("{:04}.json", index)
+}
+
+// Fixture files of `dir`, in the order of their numbers, so `10000.json` follows `9999.json`.
+// Files not named by a number follow, by name
+fn fixture_files(dir: &Path) -> Result<Vec<PathBuf>, BigQueryError> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|err| fixture_error(dir, err))? {
+        let path = entry.map_err(|err| fixture_error(dir, err))?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            files.push(path);
+        }
+    }
+    files.sort_by_cached_key(|path| {
+        let index = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        (index.is_none(), index, path.clone())
+    });
+    Ok(files)
+}
+
+pub(crate) enum Fixtures {
+    Record {
+        dir: PathBuf,
+        next_index: AtomicUsize,
+    },
+    Replay {
+        exchanges: Mutex<HashMap<Key, VecDeque<Exchange>>>,
+        // Requests received, in order, for the tests checking the calls made
+        #[cfg(test)]
+        requests: Mutex<Vec<Key>>,
+    },
+}
+
+impl Fixtures {
+    // Creates `dir` if needed, refusing to mix new fixtures with the ones of another recording
+    pub(crate) fn record(dir: &Path) -> Result<Self, BigQueryError> {
+        std::fs::create_dir_all(dir).map_err(|err| fixture_error(dir, err))?;
+        if !fixture_files(dir)?.is_empty() {
+            return Err(fixture_error(dir, "the directory already holds fixtures"));
+        }
+        Ok(Fixtures::Record {
+            dir: dir.to_path_buf(),
+            next_index: AtomicUsize::new(0),
+        })
+    }
+    pub(crate) fn replay(dir: &Path) -> Result<Self, BigQueryError> {
+        let mut exchanges: HashMap<Key, VecDeque<Exchange>> = HashMap::new();
+        for path in fixture_files(dir)? {
+            let bytes = std::fs::read(&path).map_err(|err| fixture_error(&path, err))?;
+            let exchange: Exchange =
+                serde_json::from_slice(&bytes).map_err(|err| fixture_error(&path, err))?;
+            exchanges
+                .entry(exchange.key())
+                .or_default()
+                .push_back(exchange);
+        }
+        Ok(Fixtures::Replay {
+            exchanges: Mutex::new(exchanges),
+            #[cfg(test)]
+            requests: Mutex::default(),
+        })
+    }
+    // Method, path and query string of the requests replayed so far
+    #[cfg(test)]
+    pub(crate) fn requests(&self) -> Vec<Key> {
+        match self {
+            Fixtures::Record { .. } => Vec::new(),
+            Fixtures::Replay { requests, .. } => requests.lock().unwrap().clone(),
+        }
+    }
+    /// Sends the request and saves the exchange, or answers it from the recorded ones.
+    pub(crate) async fn send(
+        &self,
+        client: &reqwest::Client,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, BigQueryError> {
+        match self {
+            Fixtures::Record { dir, next_index } => {
+                let (method, path, query) = request_key(&request);
+                let request_body = request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .map(serde_json::from_slice)
+                    .transpose()?;
+                let res = client.execute(request).await?;
+                let status = res.status().as_u16();
+                let bytes = res.bytes().await?;
+                let response_body = serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+                    serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+                });
+                let exchange = Exchange {
+                    method,
+                    path,
+                    query,
+                    request_body,
+                    status,
+                    response_body,
+                };
+                let index = next_index.fetch_add(1, Ordering::Relaxed);
+                let file = dir.join(fixture_name(index));
+                tokio::fs::write(&file, serde_json::to_vec_pretty(&exchange)?)
+                    .await
+                    .map_err(|err| fixture_error(&file, err))?;
+                exchange.response()
+            }
+            Fixtures::Replay {
+                exchanges,
+                #[cfg(test)]
+                requests,
+            } => {
+                let key = request_key(&request);
+                #[cfg(test)]
+                requests.lock().unwrap().push(key.clone());
+                let mut exchanges = exchanges.lock().unwrap();
+                let queue =
+                    exchanges
+                        .get_mut(&key)
+                        .ok_or_else(|| BigQueryError::MissingFixture {
+                            method: key.0.clone(),
+                            url: request.url().to_string(),
+                        })?;
+                let exchange = if queue.len() > 1 {
+                    queue.pop_front().unwrap()
+                } else {
+                    queue[0].clone()
+                };
+                exchange.response()
+            }
+        }
+    }
+}
+
+/// Fixtures for the tests of the other modules, in a `project` project.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::client::Client;
+
+    /// `method` request to `path`, relative to the project, answered with `response_body`.
+    pub(crate) fn exchange(
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        response_body: serde_json::Value,
+    ) -> Exchange {
+        Exchange {
+            method: method.to_string(),
+            path: format!("/bigquery/v2/projects/project{}", path),
+            query: query.map(str::to_string),
+            request_body: None,
+            status: 200,
+            response_body,
+        }
+    }
+
+    /// jobs.insert of a query, creating `job_id`.
+    pub(crate) fn insert_job(job_id: &str) -> Exchange {
+        exchange(
+            "POST",
+            "/jobs",
+            None,
+            serde_json::json!({"jobReference": {"projectId": "project", "jobId": job_id}}),
+        )
+    }
+
+    /// A page of the results of `job_id`, `total_rows` rows of a single STRING column `name`.
+    pub(crate) fn results(
+        job_id: &str,
+        query: Option<&str>,
+        total_rows: usize,
+        names: &[&str],
+        last_page: bool,
+    ) -> Exchange {
+        let rows: Vec<_> = names
+            .iter()
+            .map(|name| serde_json::json!({"f": [{"v": name}]}))
+            .collect();
+        let mut body = serde_json::json!({
+            "jobComplete": true,
+            "totalRows": total_rows.to_string(),
+            "schema": {"fields": [{"name": "name", "type": "STRING", "mode": "NULLABLE"}]},
+            "rows": rows,
+        });
+        if !last_page {
+            body["pageToken"] = "next".into();
+        }
+        exchange("GET", &format!("/queries/{}", job_id), query, body)
+    }
+
+    /// The requests replayed by `client` so far, as `METHOD path?query` with the path relative to
+    /// the project.
+    pub(crate) fn requests(client: &Client) -> Vec<String> {
+        let fixtures = client.fixtures().expect("a replaying client");
+        fixtures
+            .requests()
+            .into_iter()
+            .map(|(method, path, query)| {
+                let path = path.trim_start_matches("/bigquery/v2/projects/project");
+                match query {
+                    Some(query) => format!("{} {}?{}", method, path, query),
+                    None => format!("{} {}", method, path),
+                }
+            })
+            .collect()
+    }
+
+    /// jobs.cancel of `job_id`.
+    pub(crate) fn cancel_job(job_id: &str) -> Exchange {
+        exchange(
+            "POST",
+            &format!("/jobs/{}/cancel", job_id),
+            None,
+            serde_json::json!({"job": {"jobReference": {"projectId": "project", "jobId": job_id}}}),
+        )
+    }
+
+    /// Writes `exchanges` as the fixtures of a new directory.
+    pub(crate) fn write_fixtures(name: &str, exchanges: &[Exchange]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("my_bq_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, exchange) in exchanges.iter().enumerate() {
+            let bytes = serde_json::to_vec_pretty(exchange).unwrap();
+            std::fs::write(dir.join(fixture_name(i)), bytes).unwrap();
+        }
+        dir
+    }
+
+    /// A client replaying `exchanges`, `name` naming the temporary fixture directory.
+    pub(crate) fn replay_client(name: &str, exchanges: &[Exchange]) -> Client {
+        let dir = write_fixtures(name, exchanges);
+        let client = Client::replay_from(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use crate::TableRow;
+
+    async fn get(fixtures: &Fixtures, url: &str) -> Result<serde_json::Value, BigQueryError> {
+        let client = reqwest::Client::new();
+        let request = client.get(url).build()?;
+        Ok(fixtures.send(&client, request).await?.json().await?)
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let poll = |query, job_complete| {
+            exchange(
+                "GET",
+                "/queries/job_1",
+                Some(query),
+                serde_json::json!({ "jobComplete": job_complete }),
+            )
+        };
+        let dir = write_fixtures(
+            "replay",
+            &[
+                poll("location=EU", false),
+                poll("location=EU", true),
+                poll("maxResults=10", true),
+            ],
+        );
+        assert!(matches!(
+            Fixtures::record(&dir),
+            Err(BigQueryError::FixtureError { .. })
+        ));
+        let fixtures = Fixtures::replay(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let url = "https://bigquery.googleapis.com/bigquery/v2/projects/project/queries/job_1";
+        let poll = format!("{}?location=EU", url);
+        // Polls get the responses in order, then the last one again
+        for job_complete in [false, true, true] {
+            assert_eq!(
+                get(&fixtures, &poll).await.unwrap(),
+                serde_json::json!({ "jobComplete": job_complete })
+            );
+        }
+        assert!(get(&fixtures, &format!("{}?maxResults=10", url))
+            .await
+            .is_ok());
+        assert!(matches!(
+            get(&fixtures, url).await,
+            Err(BigQueryError::MissingFixture { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fixture_files_order() {
+        let dir = write_fixtures("fixture_files_order", &[]);
+        for name in [
+            "10000.json",
+            "notes.json",
+            "9999.json",
+            "0001.json",
+            "README.md",
+        ] {
+            std::fs::write(dir.join(name), b"{}").unwrap();
+        }
+        let files = fixture_files(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["0001.json", "9999.json", "10000.json", "notes.json"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_client() {
+        let client = replay_client(
+            "replay_client",
+            &[
+                insert_job("job_1"),
+                exchange(
+                    "GET",
+                    "/queries/job_1",
+                    None,
+                    serde_json::json!({"jobComplete": false}),
+                ),
+                results("job_1", None, 2, &["a"], false),
+                results("job_1", Some("maxResults=1&startIndex=1"), 2, &["b"], true),
+            ],
+        );
+        let (schema, rows) = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap()
+            .get_results_with_schema::<TableRow>()
+            .await
+            .unwrap();
+        assert_eq!(schema.fields[0].name, "name");
+        let expected: Vec<TableRow> =
+            serde_json::from_value(serde_json::json!([{"f": [{"v": "a"}]}, {"f": [{"v": "b"}]}]))
+                .unwrap();
+        assert_eq!(rows, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replay_failed_page() {
+        // The page of 1000 rows starting at 1 is missing
+        let client = replay_client(
+            "replay_failed_page",
+            &[
+                insert_job("job_1"),
+                results("job_1", None, 1002, &["a"], false),
+                results(
+                    "job_1",
+                    Some("maxResults=1&startIndex=1001"),
+                    1002,
+                    &["a"],
+                    true,
+                ),
+                cancel_job("job_1"),
+            ],
+        );
+        let res = client
+            .post_query("project", "SELECT name FROM names".to_string())
+            .await
+            .unwrap()
+            .with_cancel_on_abort()
+            .get_results::<TableRow>()
+            .await;
+        match res {
+            Err(BigQueryError::MissingFixture { url, .. }) => {
+                assert!(url.ends_with("?maxResults=1000&startIndex=1"))
+            }
+            res => panic!(
+                "Expected a missing fixture, got {:?}",
+                res.map(|rows| rows.len())
+            ),
+        }
+        // The failure cancels the job before being returned
+        let requests = requests(&client);
+        assert_eq!(requests.last().unwrap(), "POST /jobs/job_1/cancel");
+    }
+}
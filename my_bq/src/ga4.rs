@@ -0,0 +1,238 @@
+//! Row types and queries for the GA4 and Firebase Analytics BigQuery export, stored as daily
+//! `events_YYYYMMDD` tables in an `analytics_<property id>` dataset.
+use crate::error::BigQueryError;
+use crate::query::Select;
+use crate::{Deserialize, Schema, Serialize};
+
+/// The value of an event parameter, only one of the columns is set.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+#[my_bq(ignore_extra_columns)]
+pub struct EventValue {
+    pub string_value: Option<String>,
+    pub int_value: Option<i64>,
+    pub float_value: Option<f64>,
+    pub double_value: Option<f64>,
+}
+
+impl EventValue {
+    pub fn as_str(&self) -> Option<&str> {
+        self.string_value.as_deref()
+    }
+    pub fn as_int(&self) -> Option<i64> {
+        self.int_value
+    }
+    /// The float or double value, GA4 stores most numbers as doubles.
+    pub fn as_float(&self) -> Option<f64> {
+        self.double_value.or(self.float_value)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+pub struct EventParam {
+    pub key: String,
+    pub value: EventValue,
+}
+
+/// The value of a user property, with the time it was last set.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+#[my_bq(ignore_extra_columns)]
+pub struct UserPropertyValue {
+    #[my_bq(flatten)]
+    pub value: EventValue,
+    pub set_timestamp_micros: Option<i64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+pub struct UserProperty {
+    pub key: String,
+    pub value: UserPropertyValue,
+}
+
+/// Consent state of the event, "Yes", "No" or null when unknown.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+#[my_bq(ignore_extra_columns)]
+pub struct PrivacyInfo {
+    pub analytics_storage: Option<String>,
+    pub ads_storage: Option<String>,
+    pub uses_transient_token: Option<String>,
+}
+
+/// The common columns of an export row. Other columns are ignored; structs needing more of them
+/// can flatten this one.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, Schema)]
+#[my_bq(ignore_extra_columns)]
+pub struct Event {
+    pub event_date: String,
+    pub event_timestamp: i64,
+    pub event_name: String,
+    pub event_params: Vec<EventParam>,
+    pub user_id: Option<String>,
+    pub user_pseudo_id: Option<String>,
+    pub user_properties: Vec<UserProperty>,
+    pub privacy_info: Option<PrivacyInfo>,
+}
+
+/// The value of the first parameter named `key`.
+pub fn find_param<'a>(params: &'a [EventParam], key: &str) -> Option<&'a EventValue> {
+    params
+        .iter()
+        .find(|param| param.key == key)
+        .map(|param| &param.value)
+}
+
+impl Event {
+    pub fn param(&self, key: &str) -> Option<&EventValue> {
+        find_param(&self.event_params, key)
+    }
+    pub fn param_str(&self, key: &str) -> Option<&str> {
+        self.param(key).and_then(EventValue::as_str)
+    }
+    pub fn param_int(&self, key: &str) -> Option<i64> {
+        self.param(key).and_then(EventValue::as_int)
+    }
+    pub fn param_float(&self, key: &str) -> Option<f64> {
+        self.param(key).and_then(EventValue::as_float)
+    }
+    pub fn user_property(&self, key: &str) -> Option<&UserPropertyValue> {
+        self.user_properties
+            .iter()
+            .find(|property| property.key == key)
+            .map(|property| &property.value)
+    }
+}
+
+// Table suffix of a date given as "YYYYMMDD" or "YYYY-MM-DD"
+fn table_suffix(date: &str) -> Result<String, BigQueryError> {
+    let suffix: String = match date.len() {
+        10 if date.as_bytes()[4] == b'-' && date.as_bytes()[7] == b'-' => date.replace('-', ""),
+        _ => date.to_string(),
+    };
+    if suffix.len() != 8 || !suffix.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(BigQueryError::ValueConversionError {
+            value: date.to_string(),
+            expected: "date as YYYYMMDD or YYYY-MM-DD",
+        });
+    }
+    Ok(suffix)
+}
+
+/// `_TABLE_SUFFIX BETWEEN '<start>' AND '<end>'`, the condition reading the daily tables from
+/// `start` to `end` included out of `events_*`. Dates are given as "YYYYMMDD" or "YYYY-MM-DD".
+/// The intraday tables, with suffixes like `intraday_20221018`, aren't matched.
+pub fn table_suffix_between(start: &str, end: &str) -> Result<String, BigQueryError> {
+    Ok(format!(
+        "_TABLE_SUFFIX BETWEEN '{}' AND '{}'",
+        table_suffix(start)?,
+        table_suffix(end)?
+    ))
+}
+
+/// Selects the columns of `T` from the daily tables of `dataset`, like
+/// `my-project.analytics_123456`, from `start` to `end` included.
+pub fn events_between<T: crate::client::Deserialize>(
+    dataset: &str,
+    start: &str,
+    end: &str,
+) -> Result<Select, BigQueryError> {
+    Ok(Select::from_table::<T>(&format!("{}.events_*", dataset))
+        .filter(table_suffix_between(start, end)?))
+}
+
+/// Selects the columns of `T` from the intraday tables of `dataset`, holding the events of the
+/// current day until they're moved to the daily table.
+pub fn intraday_events<T: crate::client::Deserialize>(dataset: &str) -> Select {
+    Select::from_table::<T>(&format!("{}.events_intraday_*", dataset))
+}
+
+/// Expression reading the parameter `key` of an event as `value_column`, like `string_value`,
+/// to filter on or select it:
+/// `(SELECT value.string_value FROM UNNEST(event_params) WHERE key = 'page_location')`.
+pub fn param_expression(key: &str, value_column: &str) -> String {
+    format!(
+        "(SELECT value.{} FROM UNNEST(event_params) WHERE key = '{}')",
+        value_column,
+        key.replace('\\', "\\\\").replace('\'', "\\'")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Deserialize as _, Schema as _};
+    use crate::structs::table_row::TableRow;
+    use crate::structs::table_schema::TableSchema;
+
+    #[test]
+    fn test_event() {
+        let schema: TableSchema =
+            serde_json::from_str(include_str!("../tests/schemas/events.json")).unwrap();
+        // The test schema lacks user_id, user_properties and privacy_info
+        let mut fields = schema.fields;
+        let missing = ["user_id", "user_properties", "privacy_info"];
+        fields.extend(
+            Event::schema_fields()
+                .into_iter()
+                .filter(|field| missing.contains(&field.name.as_str())),
+        );
+        let row = r#"{"f": [
+            {"v": "20221018"}, {"v": "1666051200000000"}, {"v": "page_view"},
+            {"v": [
+              {"v": {"f": [{"v": "page_location"}, {"v": {"f": [{"v": "https://example.com"}, {"v": null}, {"v": null}, {"v": null}]}}]}},
+              {"v": {"f": [{"v": "engagement_time_msec"}, {"v": {"f": [{"v": null}, {"v": "1500"}, {"v": null}, {"v": null}]}}]}},
+              {"v": {"f": [{"v": "value"}, {"v": {"f": [{"v": null}, {"v": null}, {"v": null}, {"v": "9.99"}]}}]}}
+            ]},
+            {"v": "123.456"}, {"v": "user1"},
+            {"v": [
+              {"v": {"f": [{"v": "plan"}, {"v": {"f": [{"v": "pro"}, {"v": null}, {"v": null}, {"v": null}, {"v": "1666051100000000"}]}}]}}
+            ]},
+            {"v": {"f": [{"v": "Yes"}, {"v": "No"}, {"v": null}]}}
+          ]}"#;
+        let row: TableRow = serde_json::from_str(row).unwrap();
+        let decoder = Event::create_deserialize_indices(&fields).unwrap();
+        let event = Event::deserialize(row, decoder.root()).unwrap();
+        assert_eq!(
+            event.param_str("page_location"),
+            Some("https://example.com")
+        );
+        assert_eq!(event.param_int("engagement_time_msec"), Some(1500));
+        assert_eq!(event.param_float("value"), Some(9.99));
+        assert_eq!(event.param_str("value"), None);
+        assert_eq!(event.param("missing"), None);
+        let plan = event.user_property("plan").unwrap();
+        assert_eq!(plan.value.as_str(), Some("pro"));
+        assert_eq!(plan.set_timestamp_micros, Some(1666051100000000));
+        assert_eq!(
+            event.privacy_info.unwrap().analytics_storage.as_deref(),
+            Some("Yes")
+        );
+    }
+
+    #[test]
+    fn test_events_between() {
+        assert_eq!(
+            table_suffix_between("2022-10-01", "20221018").unwrap(),
+            "_TABLE_SUFFIX BETWEEN '20221001' AND '20221018'"
+        );
+        assert!(table_suffix_between("2022-10", "20221018").is_err());
+        assert!(table_suffix_between("20221001", "2022101'").is_err());
+        let query = events_between::<EventParam>("project.analytics_1", "20221001", "20221018")
+            .unwrap()
+            .filter(format!(
+                "{} LIKE '%/checkout'",
+                param_expression("page_location", "string_value")
+            ));
+        assert_eq!(
+            query.to_string(),
+            "SELECT `key`, STRUCT(`value`.`string_value` AS `string_value`, \
+            `value`.`int_value` AS `int_value`, `value`.`float_value` AS `float_value`, \
+            `value`.`double_value` AS `double_value`) AS `value` \
+            FROM `project.analytics_1.events_*` \
+            WHERE (_TABLE_SUFFIX BETWEEN '20221001' AND '20221018') \
+            AND ((SELECT value.string_value FROM UNNEST(event_params) WHERE key = 'page_location') LIKE '%/checkout')"
+        );
+        assert_eq!(
+            param_expression("it's", "int_value"),
+            "(SELECT value.int_value FROM UNNEST(event_params) WHERE key = 'it\\'s')"
+        );
+    }
+}
@@ -1,9 +1,17 @@
+pub mod blocking;
 pub mod client;
 pub mod error;
+pub mod fixtures;
+pub mod ga4;
+pub mod metrics;
+pub mod query;
 pub mod structs;
+pub mod types;
 
 pub use error::BigQueryError;
-pub use my_bq_proc::Deserialize;
+pub use my_bq_proc::{Deserialize, Schema, Serialize};
+// Used by the code generated by `#[derive(Serialize)]`
+pub use serde_json;
 pub use structs::table_row::TableRow;
 
 extern crate self as my_bq;
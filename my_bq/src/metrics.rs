@@ -0,0 +1,21 @@
+//! Metric names reported through the [`Metrics`] hooks.
+
+/// Histogram of the jobs.insert latencies, in seconds.
+pub const JOB_INSERT_LATENCY: &str = "bigquery_job_insert_latency_seconds";
+/// Counter of the polls of unfinished jobs.
+pub const POLL_ATTEMPTS: &str = "bigquery_poll_attempts_total";
+/// Histogram of the poll latencies, in seconds.
+pub const POLL_LATENCY: &str = "bigquery_poll_latency_seconds";
+/// Histogram of the result page fetch latencies, in seconds.
+pub const PAGE_LATENCY: &str = "bigquery_page_latency_seconds";
+/// Histogram of the result page sizes, in bytes.
+pub const PAGE_BYTES: &str = "bigquery_page_bytes";
+/// Counter of the requests retried after a transport error.
+pub const REQUEST_RETRIES: &str = "bigquery_request_retries_total";
+
+/// Hooks for plugging the client into a metrics backend (prometheus, statsd, ...).
+/// Install with [`crate::client::Client::with_metrics`].
+pub trait Metrics: Send + Sync {
+    fn increment_counter(&self, name: &'static str, value: u64);
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
@@ -0,0 +1,243 @@
+//! SELECT queries generated from the structs reading their results, so the columns of a query
+//! and the fields of its row type can't drift apart.
+use std::fmt;
+
+use crate::client::Deserialize;
+
+/// A column read by a struct deriving `Deserialize`, see [`Deserialize::select_columns`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectColumn {
+    pub name: String,
+    /// The field holds a `Vec`
+    pub repeated: bool,
+    /// The field holds an `Option`
+    pub nullable: bool,
+    /// Sub-columns read from a record, empty to select the whole column
+    pub fields: Vec<SelectColumn>,
+}
+
+impl SelectColumn {
+    // Expression producing the value of the column from `source`. Records only keep the fields
+    // read by the struct: `STRUCT(...)` for single records, `ARRAY(SELECT AS STRUCT ...)` for
+    // repeated ones, keeping the order of the items.
+    fn expression(&self, source: &str, depth: usize) -> String {
+        if self.fields.is_empty() {
+            return source.to_string();
+        }
+        if self.repeated {
+            let item = format!("`_item{}`", depth);
+            let offset = format!("`_offset{}`", depth);
+            format!(
+                "ARRAY(SELECT AS STRUCT {} FROM UNNEST({}) AS {} WITH OFFSET AS {} ORDER BY {})",
+                select_list(&self.fields, &format!("{}.", item), depth + 1),
+                source,
+                item,
+                offset,
+                offset
+            )
+        } else {
+            let record = format!(
+                "STRUCT({})",
+                select_list(&self.fields, &format!("{}.", source), depth + 1)
+            );
+            if self.nullable {
+                format!("IF({} IS NULL, NULL, {})", source, record)
+            } else {
+                record
+            }
+        }
+    }
+}
+
+// Comma separated expressions of `columns`, read from the record `prefix`
+fn select_list(columns: &[SelectColumn], prefix: &str, depth: usize) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let name = quote_identifier(&column.name);
+            let source = format!("{}{}", prefix, name);
+            let expression = column.expression(&source, depth);
+            if expression == source && prefix.is_empty() {
+                expression
+            } else {
+                format!("{} AS {}", expression, name)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "\\`"))
+}
+
+/// `SELECT <columns> FROM <table> WHERE ...` reading the columns of `T`, see [`Select::from_table`].
+/// Displays as the SQL text passed to `post_query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Select {
+    columns: Vec<SelectColumn>,
+    table: String,
+    filters: Vec<String>,
+    order_by: Vec<String>,
+    limit: Option<u64>,
+}
+
+impl Select {
+    /// Selects the columns read by `T` from `table`, like `project.dataset.table` or a wildcard
+    /// table like `project.dataset.events_*`. The table is quoted with backticks unless it
+    /// already is. Fields with `#[my_bq(rename)]` select their column name, records only select
+    /// the sub-columns read by their type, and flattened structs add their own columns.
+    pub fn from_table<T: Deserialize>(table: &str) -> Self {
+        let table = if table.starts_with('`') {
+            table.to_string()
+        } else {
+            quote_identifier(table)
+        };
+        Select {
+            columns: T::select_columns(),
+            table,
+            filters: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        }
+    }
+    /// Adds a condition to the WHERE clause, conditions are combined with AND. The condition is
+    /// inserted as is, values from untrusted input must be escaped by the caller.
+    pub fn filter(mut self, condition: impl Into<String>) -> Self {
+        self.filters.push(condition.into());
+        self
+    }
+    /// Adds an expression to the ORDER BY clause, like `event_timestamp DESC`.
+    pub fn order_by(mut self, expression: impl Into<String>) -> Self {
+        self.order_by.push(expression.into());
+        self
+    }
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.columns.is_empty() {
+            write!(f, "SELECT * FROM {}", self.table)?;
+        } else {
+            write!(
+                f,
+                "SELECT {} FROM {}",
+                select_list(&self.columns, "", 0),
+                self.table
+            )?;
+        }
+        if !self.filters.is_empty() {
+            let filters: Vec<String> = self
+                .filters
+                .iter()
+                .map(|filter| format!("({})", filter))
+                .collect();
+            write!(f, " WHERE {}", filters.join(" AND "))?;
+        }
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY {}", self.order_by.join(", "))?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use my_bq_proc::Deserialize;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Value {
+        string_value: Option<String>,
+        int_value: Option<i64>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Param {
+        key: String,
+        value: Value,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Device {
+        category: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Ids {
+        #[my_bq(rename = "user_pseudo_id")]
+        pseudo_id: String,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Event {
+        event_name: String,
+        #[my_bq(rename = "event_params")]
+        params: Vec<Param>,
+        device: Option<Device>,
+        #[my_bq(flatten)]
+        ids: Ids,
+        #[my_bq(skip)]
+        processed: bool,
+    }
+
+    #[test]
+    fn test_select_columns() {
+        assert_eq!(
+            Event::select_columns()[0],
+            SelectColumn {
+                name: "event_name".to_string(),
+                repeated: false,
+                nullable: false,
+                fields: Vec::new(),
+            }
+        );
+        let names: Vec<_> = Event::select_columns()
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["event_name", "event_params", "device", "user_pseudo_id"]
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let query = Select::from_table::<Event>("project.analytics.events_*")
+            .filter("_TABLE_SUFFIX = '20221018'")
+            .filter("event_name = 'page_view' OR event_name = 'purchase'")
+            .order_by("event_timestamp DESC")
+            .limit(10);
+        assert_eq!(
+            query.to_string(),
+            "SELECT `event_name`, \
+            ARRAY(SELECT AS STRUCT `_item0`.`key` AS `key`, \
+            STRUCT(`_item0`.`value`.`string_value` AS `string_value`, \
+            `_item0`.`value`.`int_value` AS `int_value`) AS `value` \
+            FROM UNNEST(`event_params`) AS `_item0` WITH OFFSET AS `_offset0` ORDER BY `_offset0`) \
+            AS `event_params`, \
+            IF(`device` IS NULL, NULL, STRUCT(`device`.`category` AS `category`)) AS `device`, \
+            `user_pseudo_id` \
+            FROM `project.analytics.events_*` \
+            WHERE (_TABLE_SUFFIX = '20221018') AND (event_name = 'page_view' OR event_name = 'purchase') \
+            ORDER BY event_timestamp DESC LIMIT 10"
+        );
+        assert_eq!(
+            Select::from_table::<Device>("`project.dataset.devices`").to_string(),
+            "SELECT `category` FROM `project.dataset.devices`"
+        );
+    }
+}
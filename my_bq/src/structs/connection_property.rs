@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/ConnectionProperty
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionProperty {
+    pub key: String,
+    pub value: String,
+}
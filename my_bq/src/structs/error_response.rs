@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+// Body of the API responses with an error status
+// https://cloud.google.com/apis/design/errors#http_mapping
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub error: ErrorStatus,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorStatus {
+    pub code: Option<u16>,
+    pub message: String,
+    pub status: Option<String>,
+}
@@ -1,6 +1,7 @@
 use crate::structs::job_configuration::JobConfiguration;
 use crate::structs::job_configuration_query::JobConfigurationQuery;
 use crate::structs::job_reference::JobReference;
+use crate::structs::job_statistics::JobStatistics;
 use crate::structs::job_status::JobStatus;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +13,8 @@ pub struct Job {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub job_reference: Option<JobReference>,
     pub status: Option<JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<JobStatistics>,
 }
 
 impl Job {
@@ -21,10 +24,13 @@ impl Job {
                 query: Some(JobConfigurationQuery {
                     query: Some(query),
                     use_legacy_sql: Some(false),
+                    create_session: None,
+                    connection_properties: None,
                 }),
             }),
             job_reference: None,
             status: None,
+            statistics: None,
         }
     }
 }
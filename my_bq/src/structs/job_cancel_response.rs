@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+use crate::structs::job::Job;
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/jobs/cancel
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobCancelResponse {
+    pub job: Option<Job>,
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::structs::connection_property::ConnectionProperty;
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JobConfigurationQuery {
@@ -9,4 +11,10 @@ pub struct JobConfigurationQuery {
     // Changes syntax of SQL query. See https://cloud.google.com/bigquery/docs/reference/legacy-sql for details
     #[serde(skip_serializing_if = "Option::is_none")]
     pub use_legacy_sql: Option<bool>,
+    // Starts a new session, whose id is returned in the job statistics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_session: Option<bool>,
+    // Session id is passed via the "session_id" property
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_properties: Option<Vec<ConnectionProperty>>,
 }
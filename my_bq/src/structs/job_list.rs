@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::structs::job::Job;
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/jobs/list
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobList {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+}
@@ -1,3 +1,4 @@
+use crate::structs::raw_row::RawRow;
 use crate::structs::table_row::TableRow;
 use serde::{Deserialize, Serialize};
 
@@ -15,3 +16,15 @@ pub struct JobQueryResults {
     pub schema: Option<TableSchema>,
     pub rows: Option<Vec<TableRow>>,
 }
+
+// Same as JobQueryResults, with rows borrowing the response bytes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawQueryResults<'a> {
+    pub total_rows: Option<String>,
+    pub page_token: Option<String>,
+    pub job_complete: bool,
+    pub schema: Option<TableSchema>,
+    #[serde(borrow)]
+    pub rows: Option<Vec<RawRow<'a>>>,
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/Job#JobStatistics
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatistics {
+    // Milliseconds since the epoch, encoded as int64 strings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creation_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes_processed: Option<String>,
+    // Set on script jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_child_jobs: Option<String>,
+    // Set on child jobs of a script
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<QueryStatistics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script_statistics: Option<ScriptStatistics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_info: Option<SessionInfo>,
+}
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/Job#JobStatistics2
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryStatistics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes_processed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_bytes_billed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_hit: Option<bool>,
+    // SELECT, INSERT, CREATE_TABLE, SCRIPT, ...
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statement_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_dml_affected_rows: Option<String>,
+}
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/Job#SessionInfo
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub session_id: String,
+}
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/Job#ScriptStatistics
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptStatistics {
+    // STATEMENT or EXPRESSION
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evaluation_kind: Option<String>,
+    #[serde(default)]
+    pub stack_frames: Vec<ScriptStackFrame>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptStackFrame {
+    pub start_line: i32,
+    pub start_column: i32,
+    pub end_line: i32,
+    pub end_column: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub procedure_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
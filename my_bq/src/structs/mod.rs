@@ -1,11 +1,18 @@
+pub mod connection_property;
 pub mod error_proto;
+pub mod error_response;
 pub mod job;
+pub mod job_cancel_response;
 pub mod job_configuration;
 pub mod job_configuration_query;
+pub mod job_list;
 pub mod job_query_results;
 pub mod job_reference;
+pub mod job_statistics;
 pub mod job_status;
+pub mod raw_row;
 pub mod row_field;
+pub mod table;
 pub mod table_field_schema;
 pub mod table_row;
 pub mod table_schema;
@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use super::row_field::{RowField, Value};
+use super::table_row::TableRow;
+
+// Same as TableRow, but borrowing the values from the response bytes instead of copying them.
+// Only strings containing escape sequences are copied. Reading the cells straight into the fields
+// with seeds driven by the decoder measured slower than building these trees: most of the time
+// goes to visiting each value, which both do, not to the allocations of the trees.
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+pub struct RawRow<'a> {
+    #[serde(rename = "f", borrow)]
+    pub fields: Vec<RawField<'a>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, PartialEq)]
+pub struct RawField<'a> {
+    #[serde(rename = "v", borrow)]
+    pub value: Option<RawValue<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawValue<'a> {
+    String(Cow<'a, str>),
+    Array(Vec<RawField<'a>>),
+    Record(RawRow<'a>),
+}
+
+impl RawRow<'_> {
+    pub fn into_table_row(self) -> TableRow {
+        TableRow {
+            fields: self
+                .fields
+                .into_iter()
+                .map(RawField::into_row_field)
+                .collect(),
+        }
+    }
+}
+
+impl RawField<'_> {
+    pub fn into_row_field(self) -> RowField {
+        RowField {
+            value: self.value.map(RawValue::into_value),
+        }
+    }
+}
+
+impl RawValue<'_> {
+    pub fn into_value(self) -> Value {
+        match self {
+            RawValue::String(val) => Value::String(val.into_owned()),
+            RawValue::Array(values) => {
+                Value::Array(values.into_iter().map(RawField::into_row_field).collect())
+            }
+            RawValue::Record(row) => Value::Record(row.into_table_row()),
+        }
+    }
+}
+
+struct RawValueVisitor;
+
+impl<'de> Visitor<'de> for RawValueVisitor {
+    type Value = RawValue<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string, an array of fields or a record")
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, val: &'de str) -> Result<Self::Value, E> {
+        Ok(RawValue::String(Cow::Borrowed(val)))
+    }
+
+    fn visit_str<E: de::Error>(self, val: &str) -> Result<Self::Value, E> {
+        Ok(RawValue::String(Cow::Owned(val.to_string())))
+    }
+
+    fn visit_string<E: de::Error>(self, val: String) -> Result<Self::Value, E> {
+        Ok(RawValue::String(Cow::Owned(val)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(RawValue::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let row = RawRow::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(RawValue::Record(row))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawValue<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RawValueVisitor)
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use crate::structs::table_schema::TableSchema;
+
+// https://cloud.google.com/bigquery/docs/reference/rest/v2/tables#Table
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Table {
+    // TABLE, VIEW, EXTERNAL, MATERIALIZED_VIEW or SNAPSHOT
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub table_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<TableSchema>,
+    // Encoded as int64 strings, not set for views
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_rows: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_bytes: Option<String>,
+    // Milliseconds since the epoch, encoded as int64 strings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creation_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified_time: Option<String>,
+}
@@ -11,7 +11,15 @@ pub enum Type {
     Float,
     Float64,
     Date,
+    Datetime,
+    Time,
+    Timestamp,
+    Numeric,
+    Bignumeric,
+    Bytes,
+    Json,
     Bool,
+    Boolean,
     Record,
 }
 
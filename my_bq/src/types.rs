@@ -0,0 +1,270 @@
+//! Conversions between BigQuery values and rust types, used by the code generated by
+//! `#[derive(Deserialize)]` and `#[derive(Serialize)]`. Conversions requiring extra dependencies
+//! are behind cargo features:
+//! `chrono` for TIMESTAMP, DATE, DATETIME and TIME; `decimal` for NUMERIC and BIGNUMERIC;
+//! `bytes` for BYTES.
+use crate::error::BigQueryError;
+
+#[cfg(any(feature = "chrono", feature = "decimal", feature = "bytes"))]
+fn conversion_error(val: &str, expected: &'static str) -> BigQueryError {
+    BigQueryError::ValueConversionError {
+        value: val.to_string(),
+        expected,
+    }
+}
+
+// TIMESTAMP values come as float seconds since the epoch, often in scientific notation,
+// like "1.648823841187011E9". Parsing through f64 loses microseconds, so the digits are shifted by hand.
+#[cfg(feature = "chrono")]
+fn parse_timestamp_micros(val: &str) -> Option<i64> {
+    let (negative, val) = match val.strip_prefix('-') {
+        Some(val) => (true, val),
+        None => (false, val),
+    };
+    let (mantissa, exponent) = match val.split_once(['E', 'e']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (val, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+    let mut micros: i128 = 0;
+    for c in int_part.chars().chain(frac_part.chars()) {
+        micros = micros
+            .checked_mul(10)?
+            .checked_add(c.to_digit(10)? as i128)?;
+    }
+    let shift = exponent + 6 - frac_part.len() as i32;
+    if shift >= 0 {
+        micros = micros.checked_mul(10i128.checked_pow(shift as u32)?)?;
+    } else {
+        micros /= 10i128.checked_pow(shift.unsigned_abs())?;
+    }
+    if negative {
+        micros = -micros;
+    }
+    i64::try_from(micros).ok()
+}
+
+#[cfg(feature = "chrono")]
+pub fn parse_timestamp(val: &str) -> Result<chrono::DateTime<chrono::Utc>, BigQueryError> {
+    use chrono::TimeZone;
+    let micros = parse_timestamp_micros(val).ok_or_else(|| conversion_error(val, "TIMESTAMP"))?;
+    chrono::Utc
+        .timestamp_opt(
+            micros.div_euclid(1_000_000),
+            (micros.rem_euclid(1_000_000) * 1000) as u32,
+        )
+        .single()
+        .ok_or_else(|| conversion_error(val, "TIMESTAMP"))
+}
+
+#[cfg(feature = "chrono")]
+pub fn parse_date(val: &str) -> Result<chrono::NaiveDate, BigQueryError> {
+    chrono::NaiveDate::parse_from_str(val, "%Y-%m-%d").map_err(|_| conversion_error(val, "DATE"))
+}
+
+// DATETIME values come as "2022-04-01T14:37:21.187011", the separator may also be a space
+#[cfg(feature = "chrono")]
+pub fn parse_datetime(val: &str) -> Result<chrono::NaiveDateTime, BigQueryError> {
+    chrono::NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(val, "%Y-%m-%d %H:%M:%S%.f"))
+        .map_err(|_| conversion_error(val, "DATETIME"))
+}
+
+#[cfg(feature = "chrono")]
+pub fn parse_time(val: &str) -> Result<chrono::NaiveTime, BigQueryError> {
+    chrono::NaiveTime::parse_from_str(val, "%H:%M:%S%.f").map_err(|_| conversion_error(val, "TIME"))
+}
+
+// BIGNUMERIC values beyond the 28 digits of precision supported by Decimal produce an error
+#[cfg(feature = "decimal")]
+pub fn parse_numeric(val: &str) -> Result<rust_decimal::Decimal, BigQueryError> {
+    use std::str::FromStr;
+    rust_decimal::Decimal::from_str(val)
+        .or_else(|_| rust_decimal::Decimal::from_scientific(val))
+        .map_err(|_| conversion_error(val, "NUMERIC"))
+}
+
+#[cfg(feature = "bytes")]
+pub fn parse_bytes(val: &str) -> Result<Vec<u8>, BigQueryError> {
+    base64::decode(val).map_err(|_| conversion_error(val, "BYTES"))
+}
+
+pub fn parse_json(val: &str) -> Result<serde_json::Value, BigQueryError> {
+    Ok(serde_json::from_str(val)?)
+}
+
+// The derives wrap the conversions needing a feature in these macros, so that a disabled feature
+// is reported on the field instead of as a missing helper of this module
+#[cfg(feature = "chrono")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_chrono {
+    ($($code:tt)*) => { $($code)* };
+}
+
+#[cfg(not(feature = "chrono"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_chrono {
+    ($($code:tt)*) => {
+        ::core::compile_error!(
+            "chrono types map to TIMESTAMP, DATE, DATETIME and TIME columns with the `chrono` feature of my_bq"
+        )
+    };
+}
+
+#[cfg(feature = "decimal")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_decimal {
+    ($($code:tt)*) => { $($code)* };
+}
+
+#[cfg(not(feature = "decimal"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_decimal {
+    ($($code:tt)*) => {
+        ::core::compile_error!(
+            "`Decimal` maps to NUMERIC and BIGNUMERIC columns with the `decimal` feature of my_bq"
+        )
+    };
+}
+
+#[cfg(feature = "bytes")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_bytes {
+    ($($code:tt)*) => { $($code)* };
+}
+
+#[cfg(not(feature = "bytes"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_bytes {
+    ($($code:tt)*) => {
+        ::core::compile_error!("`Vec<u8>` maps to BYTES columns with the `bytes` feature of my_bq")
+    };
+}
+
+// Non-finite floats aren't valid JSON numbers, BigQuery accepts them as strings
+pub fn format_float(val: f64) -> serde_json::Value {
+    if val.is_nan() {
+        serde_json::Value::String("NaN".into())
+    } else if val.is_infinite() {
+        let val = if val > 0.0 { "Infinity" } else { "-Infinity" };
+        serde_json::Value::String(val.into())
+    } else {
+        serde_json::Value::from(val)
+    }
+}
+
+#[cfg(feature = "chrono")]
+pub fn format_timestamp(val: &chrono::DateTime<chrono::Utc>) -> serde_json::Value {
+    serde_json::Value::String(val.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+}
+
+#[cfg(feature = "chrono")]
+pub fn format_date(val: &chrono::NaiveDate) -> serde_json::Value {
+    serde_json::Value::String(val.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(feature = "chrono")]
+pub fn format_datetime(val: &chrono::NaiveDateTime) -> serde_json::Value {
+    serde_json::Value::String(val.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+}
+
+#[cfg(feature = "chrono")]
+pub fn format_time(val: &chrono::NaiveTime) -> serde_json::Value {
+    serde_json::Value::String(val.format("%H:%M:%S%.f").to_string())
+}
+
+#[cfg(feature = "bytes")]
+pub fn format_bytes(val: &[u8]) -> serde_json::Value {
+    serde_json::Value::String(base64::encode(val))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_parse_timestamp_micros() {
+        assert_eq!(
+            parse_timestamp_micros("1.648823841187011E9"),
+            Some(1648823841187011)
+        );
+        assert_eq!(parse_timestamp_micros("1648823841"), Some(1648823841000000));
+        assert_eq!(
+            parse_timestamp_micros("1648823841.5"),
+            Some(1648823841500000)
+        );
+        assert_eq!(parse_timestamp_micros("1.5E-5"), Some(15));
+        assert_eq!(parse_timestamp_micros("-1.0E0"), Some(-1000000));
+        assert_eq!(parse_timestamp_micros("0.0"), Some(0));
+        assert_eq!(parse_timestamp_micros("abc"), None);
+        assert_eq!(parse_timestamp_micros(""), None);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_parse_temporal() {
+        let timestamp = parse_timestamp("1.648823841187011E9").unwrap();
+        assert_eq!(timestamp.to_rfc3339(), "2022-04-01T14:37:21.187011+00:00");
+        assert_eq!(
+            parse_date("2022-04-01").unwrap(),
+            chrono::NaiveDate::from_ymd_opt(2022, 4, 1).unwrap()
+        );
+        assert_eq!(
+            parse_datetime("2022-04-01T14:37:21.187011").unwrap(),
+            parse_datetime("2022-04-01 14:37:21.187011").unwrap()
+        );
+        assert_eq!(
+            parse_time("14:37:21").unwrap(),
+            chrono::NaiveTime::from_hms_opt(14, 37, 21).unwrap()
+        );
+        assert!(parse_date("01/04/2022").is_err());
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn test_parse_numeric() {
+        assert_eq!(parse_numeric("123.456").unwrap().to_string(), "123.456");
+        assert!(parse_numeric("1e100").is_err());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("aGVsbG8=").unwrap(), b"hello");
+        assert!(parse_bytes("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(format_float(1.5), serde_json::json!(1.5));
+        assert_eq!(format_float(f64::NAN), "NaN");
+        assert_eq!(format_float(f64::NEG_INFINITY), "-Infinity");
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_format_temporal_roundtrip() {
+        let timestamp = parse_timestamp("1.648823841187011E9").unwrap();
+        assert_eq!(format_timestamp(&timestamp), "2022-04-01T14:37:21.187011Z");
+        let datetime = parse_datetime("2022-04-01T14:37:21.187011").unwrap();
+        assert_eq!(format_datetime(&datetime), "2022-04-01T14:37:21.187011");
+        let time = parse_time("14:37:21").unwrap();
+        assert_eq!(format_time(&time), "14:37:21");
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let value = parse_json(r#"{"a": [1, 2]}"#).unwrap();
+        assert_eq!(value["a"][1], 2);
+    }
+}
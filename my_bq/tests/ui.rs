@@ -0,0 +1,12 @@
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
+
+#[cfg(not(any(feature = "chrono", feature = "decimal", feature = "bytes")))]
+#[test]
+fn ui_no_features() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/no_features/*.rs");
+}
@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    #[my_bq(skip, rename = "user_id")]
+    id: String,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    #[my_bq(rename)]
+    id: String,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    #[my_bq(rename = 5)]
+    id: String,
+}
+
+fn main() {}
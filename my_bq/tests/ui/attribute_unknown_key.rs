@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    #[my_bq(name = "user_id")]
+    id: String,
+}
+
+fn main() {}
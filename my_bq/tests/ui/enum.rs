@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+enum Storage {
+    Granted,
+    Denied(String),
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    value: Option<Option<i64>>,
+}
+
+fn main() {}
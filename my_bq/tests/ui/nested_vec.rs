@@ -0,0 +1,6 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    matrix: Vec<Vec<i64>>,
+}
+
+fn main() {}
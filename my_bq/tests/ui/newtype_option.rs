@@ -0,0 +1,4 @@
+#[derive(my_bq::Deserialize)]
+struct UserId(Option<String>);
+
+fn main() {}
@@ -0,0 +1,16 @@
+// Stand-ins for the crates, which aren't dependencies without the features
+mod chrono {
+    pub struct NaiveDate;
+}
+mod rust_decimal {
+    pub struct Decimal;
+}
+
+#[derive(my_bq::Deserialize)]
+struct Row {
+    event_date: chrono::NaiveDate,
+    price: Option<rust_decimal::Decimal>,
+    payload: Vec<u8>,
+}
+
+fn main() {}
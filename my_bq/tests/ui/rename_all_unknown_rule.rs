@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+#[my_bq(rename_all = "kebab-case")]
+struct Row {
+    user_id: String,
+}
+
+fn main() {}
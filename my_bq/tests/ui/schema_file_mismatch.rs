@@ -0,0 +1,15 @@
+use my_bq::Deserialize;
+
+// Paths are relative to the manifest of the crate being built, the trybuild project here
+#[derive(Deserialize)]
+#[my_bq(schema = "../../../../tests/schemas/events.json")]
+struct Event {
+    event_date: String,
+    event_timestamp: String,
+    event_params: Option<i64>,
+    #[my_bq(alias = "user_pseudo_id")]
+    user_id: String,
+    country: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use my_bq::Deserialize;
+
+#[derive(Deserialize)]
+#[my_bq(schema = "no/such/schema.json")]
+struct Event {
+    event_name: String,
+}
+
+fn main() {}
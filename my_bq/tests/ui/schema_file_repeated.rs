@@ -0,0 +1,11 @@
+use my_bq::Deserialize;
+
+#[derive(Deserialize)]
+#[my_bq(schema = "../../../../tests/schemas/events.json")]
+#[my_bq(ignore_extra_columns)]
+struct Event {
+    event_date: String,
+    event_params: Option<String>,
+}
+
+fn main() {}
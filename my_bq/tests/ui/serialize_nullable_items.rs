@@ -0,0 +1,9 @@
+#[derive(my_bq::Serialize)]
+struct Row {
+    flags: Vec<Option<bool>>,
+}
+
+#[derive(my_bq::Serialize)]
+struct Flags(Option<Vec<Option<bool>>>);
+
+fn main() {}
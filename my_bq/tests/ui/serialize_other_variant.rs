@@ -0,0 +1,8 @@
+#[derive(my_bq::Serialize)]
+enum Storage {
+    Granted,
+    #[my_bq(other)]
+    Unknown,
+}
+
+fn main() {}
@@ -0,0 +1,4 @@
+#[derive(my_bq::Deserialize)]
+struct Point(f64, f64);
+
+fn main() {}
@@ -0,0 +1,7 @@
+#[derive(my_bq::Deserialize)]
+struct Row {
+    user_id: String,
+    pair: (i64, i64),
+}
+
+fn main() {}